@@ -0,0 +1,154 @@
+// kyuu-bench: measures throughput and latency of a running broker over the real protocol.
+// every channel publishes to a queue of its own and fetches the message right back,
+// the time between the two is the latency of one message.
+use kyuu_client::cli::{connect, Options};
+use kyuu_client::Connection;
+use std::env;
+use std::error::Error;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: kyuu-bench [options]
+
+options:
+  --addr host:port    broker address (KYUU_ADDR)
+  --vhost name        virtual host (KYUU_VHOST)
+  --user name         login with a password (KYUU_USER)
+  --password pw       (KYUU_PASSWORD)
+  --token token       login with a token instead (KYUU_TOKEN)
+  --connections n     connections to open, 4 by default
+  --channels m        channels per connection, 4 by default
+  --messages k        messages per channel, 1000 by default
+  --size bytes        message size, 256 by default
+  --exchange name     exchange the queues are declared in, \"bench\" by default";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args
+        .first()
+        .is_some_and(|arg| arg == "help" || arg == "--help" || arg == "-h")
+    {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = run(&args) {
+        eprintln!("kyuu-bench: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args)?;
+    let connections: usize = opts.number("connections", 4)?;
+    let channels: usize = opts.number("channels", 4)?;
+    let messages: usize = opts.number("messages", 1000)?;
+    let size: usize = opts.number("size", 256)?;
+    let exchange = opts.get("exchange").unwrap_or("bench".to_string());
+
+    // everything is connected and declared before the clock starts.
+    let mut conns = Vec::new();
+    for c in 0..connections {
+        let conn = connect(&opts, &format!("kyuu-bench-{}", c))?;
+        let setup = conn.channel("setup")?;
+        setup.declare_exchange("", &exchange)?;
+        for m in 0..channels {
+            setup.declare_queue(&exchange, &queue(c, m))?;
+        }
+        conns.push(conn);
+    }
+
+    let body = vec![b'x'; size];
+    let started = Instant::now();
+    let workers: Vec<_> = conns
+        .iter()
+        .enumerate()
+        .flat_map(|(c, conn)| (0..channels).map(move |m| (c, m, conn.clone())))
+        .map(|(c, m, conn)| {
+            let exchange = exchange.clone();
+            let body = body.clone();
+            thread::spawn(move || worker(conn, &exchange, c, m, messages, &body))
+        })
+        .collect();
+
+    let mut latencies = Vec::with_capacity(connections * channels * messages);
+    for worker in workers {
+        latencies.append(&mut worker.join().map_err(|_| "a worker panicked")??);
+    }
+    let elapsed = started.elapsed();
+
+    for (c, conn) in conns.into_iter().enumerate() {
+        let setup = conn.channel("setup")?;
+        for m in 0..channels {
+            setup.drop_queue(&exchange, &queue(c, m))?;
+        }
+        conn.close()?;
+    }
+    report(connections, channels, size, elapsed, latencies);
+    Ok(())
+}
+
+fn queue(connection: usize, channel: usize) -> String {
+    format!("q{}-{}", connection, channel)
+}
+
+fn worker(
+    conn: Connection,
+    exchange: &str,
+    c: usize,
+    m: usize,
+    messages: usize,
+    body: &[u8],
+) -> Result<Vec<Duration>, String> {
+    let queue = queue(c, m);
+    let channel = conn
+        .channel(&format!("bench-{}", m))
+        .map_err(|e| e.to_string())?;
+    let mut latencies = Vec::with_capacity(messages);
+    for _ in 0..messages {
+        let sent = Instant::now();
+        channel
+            .publish(exchange, &queue, body)
+            .map_err(|e| e.to_string())?;
+        match channel.fetch(exchange, &queue).map_err(|e| e.to_string())? {
+            Some(_) => latencies.push(sent.elapsed()),
+            None => return Err(format!("{} was empty right after a publish", queue)),
+        }
+    }
+    Ok(latencies)
+}
+
+fn report(
+    connections: usize,
+    channels: usize,
+    size: usize,
+    elapsed: Duration,
+    mut latencies: Vec<Duration>,
+) {
+    latencies.sort();
+    let count = latencies.len();
+    println!(
+        "{} connection(s) x {} channel(s), {} messages of {} bytes in {:.2?}",
+        connections, channels, count, size, elapsed
+    );
+    println!(
+        "throughput  {:.0} msgs/sec",
+        count as f64 / elapsed.as_secs_f64()
+    );
+    if count == 0 {
+        return;
+    }
+    println!(
+        "latency     p50 {:.2?}  p99 {:.2?}  p999 {:.2?}  max {:.2?}",
+        percentile(&latencies, 50.0),
+        percentile(&latencies, 99.0),
+        percentile(&latencies, 99.9),
+        latencies[count - 1]
+    );
+}
+
+// nearest rank, on sorted latencies.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
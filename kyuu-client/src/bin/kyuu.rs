@@ -0,0 +1,207 @@
+// kyuu: publish, fetch and manage exchanges and queues from the shell.
+use kyuu_client::cli::{connect, Options};
+use kyuu_client::{Channel, ClientError, Route};
+use std::env;
+use std::error::Error;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::process;
+
+const USAGE: &str = "usage: kyuu <command> [options]
+
+commands:
+  publish           --path a/b/c --queue q < body
+  fetch             --path a/b/c --queue q [--count n]
+  watch             --path a/b/c --queue q
+  declare-exchange  --path a/b --name c
+  declare-queue     --path a/b/c --queue q
+  drop-exchange     --path a/b --name c
+  drop-queue        --path a/b/c --queue q
+  bind              --path a --target b/c/q --key k [--kind queue|exchange]
+                    a key of all:k=v,.. or any:k=v,.. matches the headers of messages
+  unbind            --path a --target b/c/q --key k [--kind queue|exchange]
+  bindings          --path a
+
+options:
+  --addr host:port  broker address (KYUU_ADDR)
+  --vhost name      virtual host (KYUU_VHOST)
+  --user name       login with a password (KYUU_USER)
+  --password pw     (KYUU_PASSWORD)
+  --token token     login with a token instead (KYUU_TOKEN)
+  --channel name    channel to use, \"kyuu\" by default
+  --timeout secs    how long to wait for a reply, 5 by default
+  --topic pattern   publish, fetch and watch by a topic pattern instead of --path,
+                    '*' matches one exchange and '#' any number, e.g. orders/#
+  --headers k=v,..  publish to the queues bound to --path with a match on these headers
+  --fanout scope    publish to every queue of --path instead of --queue, with a scope
+                    of \"exchange\", or \"tree\" for the exchanges below it as well
+
+fetch exits with 2 when the queue is empty.";
+
+const COMMANDS: [&str; 10] = [
+    "publish",
+    "fetch",
+    "watch",
+    "declare-exchange",
+    "declare-queue",
+    "drop-exchange",
+    "drop-queue",
+    "bind",
+    "unbind",
+    "bindings",
+];
+
+// exit code of fetch when the broker replied with QUEUE_EMPTY.
+const EMPTY: i32 = 2;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match run(&args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("kyuu: {}", e);
+            1
+        }
+    };
+    process::exit(code);
+}
+
+fn run(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let command = match args.first() {
+        Some(command) if command != "help" && command != "--help" && command != "-h" => command,
+        _ => {
+            println!("{}", USAGE);
+            return Ok(0);
+        }
+    };
+    if !COMMANDS.contains(&command.as_str()) {
+        eprintln!("{}", USAGE);
+        return Err(format!("unknown command: {}", command).into());
+    }
+    let opts = Options::parse(&args[1..])?;
+    let path = opts.get("path").unwrap_or_default();
+
+    let conn = connect(&opts, "kyuu")?;
+    let channel = conn.channel(&opts.get("channel").unwrap_or("kyuu".to_string()))?;
+    let code = match command.as_str() {
+        "publish" => {
+            let mut body = Vec::new();
+            io::stdin().read_to_end(&mut body)?;
+            match opts.get("headers") {
+                Some(headers) => {
+                    let headers = headers
+                        .split(',')
+                        .map(|pair| pair.split_once('=').ok_or(format!("not k=v: {}", pair)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    channel.publish_headers(&path, &headers, &body)?
+                }
+                None => channel.publish_route(&route(&path, &opts)?, &body)?,
+            }
+            0
+        }
+        "fetch" => fetch(&channel, &path, &opts)?,
+        "watch" => {
+            // deliveries may be hours apart.
+            conn.set_read_timeout(None)?;
+            channel.subscribe_route(&route(&path, &opts)?)?;
+            loop {
+                let delivery = channel.next_delivery()?;
+                println!(
+                    "{}: {}",
+                    delivery.queue,
+                    String::from_utf8_lossy(&delivery.body)
+                );
+            }
+        }
+        "declare-exchange" => {
+            channel.declare_exchange(&path, &opts.require("name")?)?;
+            0
+        }
+        "declare-queue" => {
+            channel.declare_queue(&path, &opts.require("queue")?)?;
+            0
+        }
+        "drop-exchange" => {
+            channel.drop_exchange(&path, &opts.require("name")?)?;
+            0
+        }
+        "drop-queue" => {
+            channel.drop_queue(&path, &opts.require("queue")?)?;
+            0
+        }
+        "bind" | "unbind" => {
+            bind(&channel, command == "bind", &path, &opts)?;
+            0
+        }
+        "bindings" => {
+            for binding in channel.bindings(&path)? {
+                println!("{} {} {}", binding.kind, binding.target, binding.key);
+            }
+            0
+        }
+        _ => unreachable!(),
+    };
+    channel.close()?;
+    conn.close()?;
+    Ok(code)
+}
+
+// --target is the path of the queue or exchange, its name last.
+fn bind(channel: &Channel, bind: bool, path: &str, opts: &Options) -> Result<(), Box<dyn Error>> {
+    let target = opts.require("target")?;
+    let (target_path, name) = target.rsplit_once('/').unwrap_or(("", &target));
+    let key = opts.require("key")?;
+    match (opts.get("kind").as_deref().unwrap_or("queue"), bind) {
+        ("queue", true) => channel.bind_queue(path, target_path, name, &key)?,
+        ("queue", false) => channel.unbind_queue(path, target_path, name, &key)?,
+        ("exchange", true) => channel.bind_exchange(path, target_path, name, &key)?,
+        ("exchange", false) => channel.unbind_exchange(path, target_path, name, &key)?,
+        (kind, _) => return Err(format!("unknown binding kind: {}", kind).into()),
+    }
+    Ok(())
+}
+
+fn route(path: &str, opts: &Options) -> Result<Route, Box<dyn Error>> {
+    match opts.get("fanout").as_deref() {
+        Some("exchange") => return Ok(Route::fanout(path, false)?),
+        Some("tree") => return Ok(Route::fanout(path, true)?),
+        Some(scope) => return Err(format!("unknown fanout scope: {}", scope).into()),
+        None => {}
+    }
+    let queue = opts.require("queue")?;
+    Ok(match opts.get("topic") {
+        Some(pattern) => Route::topic(&pattern, &queue)?,
+        None => Route::new(path, &queue)?,
+    })
+}
+
+// bodies are written as they are, one per line.
+fn fetch(channel: &Channel, path: &str, opts: &Options) -> Result<i32, Box<dyn Error>> {
+    let route = route(path, opts)?;
+    let count: usize = opts.number("count", 1)?;
+    let mut stdout = io::stdout().lock();
+    for _ in 0..count {
+        let fetched = match channel.fetch_route(&route) {
+            // the broker does not answer fetches for queues it does not know.
+            Err(ClientError::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                return Err(
+                    format!("no reply, does {}/{} exist?", route.path(), route.queue()).into(),
+                )
+            }
+            fetched => fetched?,
+        };
+        match fetched {
+            Some(body) => {
+                stdout.write_all(&body)?;
+                stdout.write_all(b"\n")?;
+            }
+            None => {
+                eprintln!("kyuu: queue {} is empty", route.queue());
+                return Ok(EMPTY);
+            }
+        }
+    }
+    Ok(0)
+}
@@ -0,0 +1,378 @@
+use crate::codec;
+use crate::connection::{Connection, Delivery, Reply};
+use crate::error::ClientError;
+use crate::wire;
+use crate::wire::Route;
+use msg_queue::mq::protocol::{content, status};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// a queue or exchange bound to an exchange, as listed by Channel::bindings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    // "queue" or "exchange".
+    pub kind: String,
+    // from the root of the virtual host, the queue or exchange name last.
+    pub target: String,
+    pub key: String,
+}
+
+// a named channel on a connection. replies are matched to the channel they were asked on.
+pub struct Channel {
+    conn: Connection,
+    name: String,
+}
+
+impl Channel {
+    pub(crate) fn new(conn: Connection, name: String) -> Channel {
+        Channel { conn, name }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // path is where the exchange is created, "" for the root of the virtual host.
+    pub fn declare_exchange(&self, path: &str, name: &str) -> Result<(), ClientError> {
+        self.command(wire::NEW_EXCHANGE, path, name)
+    }
+
+    pub fn declare_queue(&self, path: &str, name: &str) -> Result<(), ClientError> {
+        self.command(wire::NEW_QUEUE, path, name)
+    }
+
+    pub fn drop_exchange(&self, path: &str, name: &str) -> Result<(), ClientError> {
+        self.command(wire::DROP_EXCHANGE, path, name)
+    }
+
+    pub fn drop_queue(&self, path: &str, name: &str) -> Result<(), ClientError> {
+        self.command(wire::DROP_QUEUE, path, name)
+    }
+
+    // messages routed to the exchange at path with a queue name equal to key also go to the
+    // queue at queue_path. a key of "#" binds every message, fanout routes ignore the key.
+    pub fn bind_queue(
+        &self,
+        path: &str,
+        queue_path: &str,
+        queue: &str,
+        key: &str,
+    ) -> Result<(), ClientError> {
+        let spec = spec("queue", queue_path, queue, key)?;
+        self.binding(wire::NEW_BINDING, path, &spec)
+    }
+
+    // matching messages are routed on through the exchange target_path/target as well.
+    pub fn bind_exchange(
+        &self,
+        path: &str,
+        target_path: &str,
+        target: &str,
+        key: &str,
+    ) -> Result<(), ClientError> {
+        let spec = spec("exchange", target_path, target, key)?;
+        self.binding(wire::NEW_BINDING, path, &spec)
+    }
+
+    pub fn unbind_queue(
+        &self,
+        path: &str,
+        queue_path: &str,
+        queue: &str,
+        key: &str,
+    ) -> Result<(), ClientError> {
+        let spec = spec("queue", queue_path, queue, key)?;
+        self.binding(wire::DROP_BINDING, path, &spec)
+    }
+
+    pub fn unbind_exchange(
+        &self,
+        path: &str,
+        target_path: &str,
+        target: &str,
+        key: &str,
+    ) -> Result<(), ClientError> {
+        let spec = spec("exchange", target_path, target, key)?;
+        self.binding(wire::DROP_BINDING, path, &spec)
+    }
+
+    // the bindings made on the exchange at path.
+    pub fn bindings(&self, path: &str) -> Result<Vec<Binding>, ClientError> {
+        let route = Route::new(path, "")?;
+        let reply = self.conn.inner.lock().unwrap().request(|inner| {
+            let head = wire::head(
+                &inner.vhost,
+                &self.name,
+                [wire::COMMAND, wire::LIST_BINDINGS, 0, 0],
+                "",
+                &route,
+                wire::SLICE_SIZE,
+            );
+            inner.send(head, &wire::body(&route, &[]))?;
+            inner.wait_reply(&self.name)
+        })?;
+        if reply.status != status::OK {
+            return Err(ClientError::Status(reply.status));
+        }
+        Ok(String::from_utf8_lossy(&reply.body)
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                Some(Binding {
+                    kind: parts.next()?.to_string(),
+                    target: parts.next()?.to_string(),
+                    key: parts.next()?.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    // the broker does not acknowledge pushes, errors show up on the next request instead.
+    // with a reconnect policy, a publish while disconnected is buffered and sent later.
+    pub fn publish(&self, path: &str, queue: &str, body: &[u8]) -> Result<(), ClientError> {
+        self.push(&Route::new(path, queue)?, body, content::UNSPECIFIED)
+    }
+
+    // like publish, for any kind of route. topic and fanout routes reach every matching queue.
+    pub fn publish_route(&self, route: &Route, body: &[u8]) -> Result<(), ClientError> {
+        self.push(route, body, content::UNSPECIFIED)
+    }
+
+    // goes to the queues bound to the exchange at path with a key like "all:region=eu"
+    // or "any:region=eu,tenant=acme" that the headers match.
+    pub fn publish_headers(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<(), ClientError> {
+        let body = wire::with_headers(headers, body)?;
+        self.push(&Route::headers(path)?, &body, content::UNSPECIFIED)
+    }
+
+    pub fn publish_json<T: Serialize>(
+        &self,
+        path: &str,
+        queue: &str,
+        value: &T,
+    ) -> Result<(), ClientError> {
+        let body = codec::encode(content::JSON, value)?;
+        self.push(&Route::new(path, queue)?, &body, content::JSON)
+    }
+
+    #[cfg(feature = "msgpack")]
+    pub fn publish_msgpack<T: Serialize>(
+        &self,
+        path: &str,
+        queue: &str,
+        value: &T,
+    ) -> Result<(), ClientError> {
+        let body = codec::encode(content::MSGPACK, value)?;
+        self.push(&Route::new(path, queue)?, &body, content::MSGPACK)
+    }
+
+    #[cfg(feature = "cbor")]
+    pub fn publish_cbor<T: Serialize>(
+        &self,
+        path: &str,
+        queue: &str,
+        value: &T,
+    ) -> Result<(), ClientError> {
+        let body = codec::encode(content::CBOR, value)?;
+        self.push(&Route::new(path, queue)?, &body, content::CBOR)
+    }
+
+    // None when the queue is empty.
+    pub fn fetch(&self, path: &str, queue: &str) -> Result<Option<Vec<u8>>, ClientError> {
+        self.fetch_route(&Route::new(path, queue)?)
+    }
+
+    // a topic route takes from the first matching queue that has a message.
+    pub fn fetch_route(&self, route: &Route) -> Result<Option<Vec<u8>>, ClientError> {
+        Ok(self.fetch_reply(route)?.map(|reply| reply.body))
+    }
+
+    // decoded according to the content type the message was published with,
+    // whichever of the publish_* helpers the publisher used.
+    pub fn fetch_as<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        queue: &str,
+    ) -> Result<Option<T>, ClientError> {
+        match self.fetch_reply(&Route::new(path, queue)?)? {
+            Some(reply) => codec::decode(reply.content_type, &reply.body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // messages of the queue are pushed to this channel until cancel() is called.
+    // subscriptions are renewed after a reconnect.
+    pub fn subscribe(&self, path: &str, queue: &str) -> Result<(), ClientError> {
+        self.subscribe_route(&Route::new(path, queue)?)
+    }
+
+    // a topic route subscribes to every queue matching it when the subscription is made.
+    pub fn subscribe_route(&self, route: &Route) -> Result<(), ClientError> {
+        self.message(wire::SUBSCRIBE, route)?;
+        self.conn
+            .inner
+            .lock()
+            .unwrap()
+            .subscribed(&self.name, route.clone());
+        Ok(())
+    }
+
+    pub fn cancel(&self, path: &str, queue: &str) -> Result<(), ClientError> {
+        self.cancel_route(&Route::new(path, queue)?)
+    }
+
+    pub fn cancel_route(&self, route: &Route) -> Result<(), ClientError> {
+        self.message(wire::CANCEL, route)?;
+        self.conn
+            .inner
+            .lock()
+            .unwrap()
+            .cancelled(&self.name, Some(route));
+        Ok(())
+    }
+
+    // blocks until the broker pushes a message for one of this channel's subscriptions.
+    // a reconnect happens in here, so consumers just keep waiting while the broker is away.
+    pub fn next_delivery(&self) -> Result<Delivery, ClientError> {
+        self.conn
+            .inner
+            .lock()
+            .unwrap()
+            .request(|inner| inner.next_delivery(&self.name))
+    }
+
+    pub fn close(self) -> Result<(), ClientError> {
+        let mut inner = self.conn.inner.lock().unwrap();
+        inner.cancelled(&self.name, None);
+        inner.request(|inner| {
+            let head = wire::head(
+                &inner.vhost,
+                &self.name,
+                [wire::NOP, 0, 0, 0],
+                "CLOSE-CH",
+                &Route::new("", "")?,
+                wire::SLICE_SIZE,
+            );
+            inner.send(head, &[])
+        })
+    }
+
+    fn push(&self, route: &Route, body: &[u8], content_type: u8) -> Result<(), ClientError> {
+        let mut inner = self.conn.inner.lock().unwrap();
+        let mut head = wire::head(
+            &inner.vhost,
+            &self.name,
+            [wire::MESSAGE, wire::PUSH, 0, 0],
+            "",
+            route,
+            wire::slice_size_for(body),
+        );
+        head.content_type = content_type;
+        inner.publish(head, &wire::body(route, body))
+    }
+
+    fn fetch_reply(&self, route: &Route) -> Result<Option<Reply>, ClientError> {
+        let reply = self.conn.inner.lock().unwrap().request(|inner| {
+            let head = wire::head(
+                &inner.vhost,
+                &self.name,
+                [wire::MESSAGE, wire::FETCH, 0, 0],
+                "",
+                route,
+                wire::SLICE_SIZE,
+            );
+            inner.send(head, &wire::body(route, &[]))?;
+            inner.wait_reply(&self.name)
+        })?;
+        match reply.status {
+            status::OK => Ok(Some(reply)),
+            status::QUEUE_EMPTY => Ok(None),
+            code => Err(ClientError::Status(code)),
+        }
+    }
+
+    // declarations are remembered, so they can be made again after a reconnect.
+    fn command(&self, kind: u8, path: &str, name: &str) -> Result<(), ClientError> {
+        wire::check_name(name)?;
+        let route = Route::new(path, "")?;
+        let mut inner = self.conn.inner.lock().unwrap();
+        inner.request(|inner| {
+            let head = wire::head(
+                &inner.vhost,
+                &self.name,
+                [wire::COMMAND, kind, 0, 0],
+                "",
+                &route,
+                wire::SLICE_SIZE,
+            );
+            inner.send(head, &wire::body(&route, name.as_bytes()))
+        })?;
+        match kind {
+            wire::NEW_EXCHANGE | wire::NEW_QUEUE => inner.declared(kind, &route.path(), name),
+            _ => inner.dropped(kind, &route.path(), name),
+        }
+        Ok(())
+    }
+
+    // bindings are remembered like declarations. the broker answers them, a target that
+    // does not exist or a binding that was never made is an error.
+    fn binding(&self, kind: u8, path: &str, spec: &str) -> Result<(), ClientError> {
+        let route = Route::new(path, "")?;
+        let mut inner = self.conn.inner.lock().unwrap();
+        let reply = inner.request(|inner| {
+            let head = wire::head(
+                &inner.vhost,
+                &self.name,
+                [wire::COMMAND, kind, 0, 0],
+                "",
+                &route,
+                wire::SLICE_SIZE,
+            );
+            inner.send(head, &wire::body(&route, spec.as_bytes()))?;
+            inner.wait_reply(&self.name)
+        })?;
+        if reply.status != status::OK {
+            return Err(ClientError::Status(reply.status));
+        }
+        match kind {
+            wire::NEW_BINDING => inner.declared(kind, &route.path(), spec),
+            _ => inner.dropped(kind, &route.path(), spec),
+        }
+        Ok(())
+    }
+
+    fn message(&self, kind: u8, route: &Route) -> Result<(), ClientError> {
+        self.conn.inner.lock().unwrap().request(|inner| {
+            let head = wire::head(
+                &inner.vhost,
+                &self.name,
+                [wire::MESSAGE, kind, 0, 0],
+                "",
+                route,
+                wire::SLICE_SIZE,
+            );
+            inner.send(head, &wire::body(route, &[]))
+        })
+    }
+}
+
+// the body of NewBinding and DropBinding: "queue a/b/q key".
+fn spec(kind: &str, path: &str, name: &str, key: &str) -> Result<String, ClientError> {
+    wire::check_name(wire::check_word(name)?)?;
+    let target = Route::new(path, name)?;
+    let target = match target.path() {
+        path if path.is_empty() => name.to_string(),
+        path => format!("{}/{}", path, name),
+    };
+    Ok(format!(
+        "{} {} {}",
+        kind,
+        wire::check_word(&target)?,
+        wire::check_word(key)?
+    ))
+}
@@ -0,0 +1,62 @@
+// option handling shared by the kyuu and kyuu-bench binaries.
+use crate::connection::Connection;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+
+// --key value pairs, with environment variables as a fallback for the connection settings.
+pub struct Options {
+    values: HashMap<String, String>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut values = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let key = arg
+                .strip_prefix("--")
+                .ok_or(format!("unexpected argument: {}", arg))?;
+            let value = args.next().ok_or(format!("missing value for --{}", key))?;
+            values.insert(key.to_string(), value.clone());
+        }
+        Ok(Options { values })
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.values.get(key).cloned().or_else(|| {
+            env::var(format!("KYUU_{}", key.to_uppercase()))
+                .ok()
+                .filter(|v| !v.is_empty())
+        })
+    }
+
+    pub fn require(&self, key: &str) -> Result<String, String> {
+        self.get(key).ok_or(format!("--{} is required", key))
+    }
+
+    pub fn number<T: FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        match self.get(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("--{} expects a number", key)),
+            None => Ok(default),
+        }
+    }
+}
+
+// --timeout is how long to wait for a reply, in seconds.
+pub fn connect(opts: &Options, client_name: &str) -> Result<Connection, Box<dyn Error>> {
+    let timeout = opts.number("timeout", 5)?;
+    let conn = Connection::connect(opts.require("addr")?, &opts.require("vhost")?)?;
+    conn.set_read_timeout(Some(Duration::from_secs(timeout)))?;
+    if let Some(token) = opts.get("token") {
+        conn.login_token(&token)?;
+    } else if let Some(user) = opts.get("user") {
+        conn.login(&user, &opts.require("password")?)?;
+    }
+    conn.set_client_name(client_name)?;
+    Ok(conn)
+}
@@ -0,0 +1,43 @@
+use crate::error::ClientError;
+use msg_queue::mq::protocol::content;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// msgpack and cbor are behind the features of the same name, json is always there.
+pub fn encode<T: Serialize>(content_type: u8, value: &T) -> Result<Vec<u8>, ClientError> {
+    match content_type {
+        content::JSON => serde_json::to_vec(value).map_err(codec),
+        #[cfg(feature = "msgpack")]
+        content::MSGPACK => rmp_serde::to_vec_named(value).map_err(codec),
+        #[cfg(feature = "cbor")]
+        content::CBOR => {
+            let mut body = Vec::new();
+            ciborium::into_writer(value, &mut body).map_err(codec)?;
+            Ok(body)
+        }
+        _ => Err(unsupported(content_type)),
+    }
+}
+
+// bodies without a content type are taken for json, that is what most publishers send.
+pub fn decode<T: DeserializeOwned>(content_type: u8, body: &[u8]) -> Result<T, ClientError> {
+    match content_type {
+        content::JSON | content::UNSPECIFIED => serde_json::from_slice(body).map_err(codec),
+        #[cfg(feature = "msgpack")]
+        content::MSGPACK => rmp_serde::from_slice(body).map_err(codec),
+        #[cfg(feature = "cbor")]
+        content::CBOR => ciborium::from_reader(body).map_err(codec),
+        _ => Err(unsupported(content_type)),
+    }
+}
+
+fn codec<E: std::fmt::Display>(e: E) -> ClientError {
+    ClientError::Codec(e.to_string())
+}
+
+fn unsupported(content_type: u8) -> ClientError {
+    ClientError::Codec(format!(
+        "unsupported content type {}",
+        content::name(content_type)
+    ))
+}
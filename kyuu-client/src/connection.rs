@@ -0,0 +1,508 @@
+use crate::channel::Channel;
+use crate::codec;
+use crate::error::ClientError;
+use crate::reconnect::ReconnectPolicy;
+use crate::wire;
+use crate::wire::Route;
+use msg_queue::mq::protocol::proto::DataHead;
+use msg_queue::mq::protocol::status;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// login and other connection-wide requests go out on the unnamed channel,
+// which is also where the broker sends errors that concern the whole connection.
+const CONTROL: &str = "";
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Reply {
+    pub status: u16,
+    pub content_type: u8,
+    pub body: Vec<u8>,
+}
+
+pub struct Delivery {
+    pub queue: String,
+    pub content_type: u8,
+    pub body: Vec<u8>,
+}
+
+impl Delivery {
+    // decoded according to the content type the message was published with.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, ClientError> {
+        codec::decode(self.content_type, &self.body)
+    }
+}
+
+#[derive(Clone)]
+enum Auth {
+    Plain(String, String),
+    Token(String),
+}
+
+// an exchange, queue or binding made through this connection, made again after a reconnect.
+// the name of a binding is its spec, "queue a/b/q key".
+#[derive(Clone, PartialEq, Eq)]
+struct Declaration {
+    kind: u8,
+    path: String,
+    name: String,
+}
+
+pub(crate) struct Inner {
+    addrs: Vec<SocketAddr>,
+    // None while disconnected.
+    stream: Option<TcpStream>,
+    read_timeout: Option<Duration>,
+    pub(crate) vhost: String,
+    // frames read while waiting for another channel, by channel name.
+    replies: HashMap<String, VecDeque<Reply>>,
+    deliveries: HashMap<String, VecDeque<Delivery>>,
+
+    // everything needed to get the session back after a reconnect.
+    policy: Option<ReconnectPolicy>,
+    auth: Option<Auth>,
+    client_name: Option<String>,
+    declarations: Vec<Declaration>,
+    subscriptions: Vec<(String, Route)>,
+    attempt: u32,
+    next_attempt: Option<Instant>,
+    // encoded publishes held back while disconnected, sent in order once connected again.
+    buffered: VecDeque<Vec<u8>>,
+    buffered_bytes: usize,
+}
+
+impl Inner {
+    pub(crate) fn send(&mut self, head: DataHead, body: &[u8]) -> Result<(), ClientError> {
+        self.write(&wire::encode(head, body))
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), ClientError> {
+        let stream = self.stream.as_mut().ok_or(ClientError::Closed)?;
+        stream.write_all(frame)?;
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<(DataHead, Vec<u8>), ClientError> {
+        let stream = self.stream.as_mut().ok_or(ClientError::Closed)?;
+        wire::read_frame(stream)
+    }
+
+    // replies come back in request order, so the next one on the channel belongs to us.
+    pub(crate) fn wait_reply(&mut self, channel: &str) -> Result<Reply, ClientError> {
+        if let Some(reply) = self.replies.get_mut(channel).and_then(|q| q.pop_front()) {
+            return Ok(reply);
+        }
+        loop {
+            let (head, body) = self.read_frame()?;
+            let from = wire::trimmed(&head.channel);
+            if wire::trimmed(&head.command) == "DELIVER" {
+                self.deliveries
+                    .entry(from)
+                    .or_default()
+                    .push_back(Delivery {
+                        queue: wire::trimmed(&head.route3),
+                        content_type: head.content_type,
+                        body: wire::unpad(&head, body),
+                    });
+                continue;
+            }
+
+            let reply = Reply {
+                status: head.msg_sign,
+                content_type: head.content_type,
+                body: wire::unpad(&head, body),
+            };
+            if from == channel {
+                return Ok(reply);
+            }
+            if from == CONTROL {
+                // token expired, shutting down, a limit was hit: the connection is going away.
+                if reply.status != status::OK {
+                    return Err(ClientError::Status(reply.status));
+                }
+                continue;
+            }
+            self.replies.entry(from).or_default().push_back(reply);
+        }
+    }
+
+    pub(crate) fn next_delivery(&mut self, channel: &str) -> Result<Delivery, ClientError> {
+        loop {
+            if let Some(delivery) = self.deliveries.get_mut(channel).and_then(|q| q.pop_front()) {
+                return Ok(delivery);
+            }
+            let (head, body) = self.read_frame()?;
+            let from = wire::trimmed(&head.channel);
+            if wire::trimmed(&head.command) == "DELIVER" {
+                self.deliveries
+                    .entry(from)
+                    .or_default()
+                    .push_back(Delivery {
+                        queue: wire::trimmed(&head.route3),
+                        content_type: head.content_type,
+                        body: wire::unpad(&head, body),
+                    });
+            } else if from == CONTROL && head.msg_sign != status::OK {
+                return Err(ClientError::Status(head.msg_sign));
+            } else {
+                self.replies.entry(from).or_default().push_back(Reply {
+                    status: head.msg_sign,
+                    content_type: head.content_type,
+                    body: wire::unpad(&head, body),
+                });
+            }
+        }
+    }
+
+    // runs op, and with a reconnect policy runs it again on a fresh connection
+    // for as long as it fails because the old one went away.
+    pub(crate) fn request<T>(
+        &mut self,
+        mut op: impl FnMut(&mut Inner) -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        loop {
+            if self.stream.is_none() {
+                self.reconnect()?;
+            }
+            match op(self) {
+                Err(e) if self.policy.is_some() && lost(&e) => self.disconnect(),
+                result => return result,
+            }
+        }
+    }
+
+    // publishes do not wait for a reconnect, they are buffered up to the policy's limit.
+    pub(crate) fn publish(&mut self, head: DataHead, body: &[u8]) -> Result<(), ClientError> {
+        let frame = wire::encode(head, body);
+        if self.stream.is_none() && self.policy.is_some() && self.due() {
+            self.try_reconnect().unwrap_or(());
+        }
+        if self.stream.is_some() {
+            match self.write(&frame) {
+                Err(e) if self.policy.is_some() && lost(&e) => self.disconnect(),
+                result => return result,
+            }
+        }
+
+        let limit = match &self.policy {
+            Some(policy) => policy.publish_buffer(),
+            None => return Err(ClientError::Closed),
+        };
+        if self.buffered_bytes + frame.len() > limit {
+            return Err(ClientError::BufferFull);
+        }
+        self.buffered_bytes += frame.len();
+        self.buffered.push_back(frame);
+        Ok(())
+    }
+
+    pub(crate) fn declared(&mut self, kind: u8, path: &str, name: &str) {
+        let declaration = Declaration {
+            kind,
+            path: path.to_string(),
+            name: name.to_string(),
+        };
+        if !self.declarations.contains(&declaration) {
+            self.declarations.push(declaration);
+        }
+    }
+
+    // whatever was declared below a dropped exchange is gone with it,
+    // and so are the bindings to a dropped queue or exchange.
+    pub(crate) fn dropped(&mut self, kind: u8, path: &str, name: &str) {
+        let kind = match kind {
+            wire::DROP_EXCHANGE => wire::NEW_EXCHANGE,
+            wire::DROP_BINDING => wire::NEW_BINDING,
+            _ => wire::NEW_QUEUE,
+        };
+        let below = if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", path, name)
+        };
+        let inside = |p: &str| p == below || p.starts_with(&format!("{}/", below));
+        self.declarations.retain(|d| {
+            let gone = d.kind == kind && d.path == path && d.name == name;
+            let nested = kind == wire::NEW_EXCHANGE && inside(&d.path);
+            let bound = d.kind == wire::NEW_BINDING
+                && kind != wire::NEW_BINDING
+                && match d.name.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["queue", target, _] if kind == wire::NEW_QUEUE => target == below,
+                    [_, target, _] => kind == wire::NEW_EXCHANGE && inside(target),
+                    _ => false,
+                };
+            !gone && !nested && !bound
+        });
+    }
+
+    pub(crate) fn subscribed(&mut self, channel: &str, route: Route) {
+        if !self
+            .subscriptions
+            .iter()
+            .any(|(c, r)| c == channel && *r == route)
+        {
+            self.subscriptions.push((channel.to_string(), route));
+        }
+    }
+
+    // None cancels every subscription of the channel.
+    pub(crate) fn cancelled(&mut self, channel: &str, route: Option<&Route>) {
+        self.subscriptions
+            .retain(|(c, r)| c != channel || route.is_some_and(|route| r != route));
+    }
+
+    fn due(&self) -> bool {
+        self.next_attempt.is_none_or(|at| Instant::now() >= at)
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            stream.shutdown(Shutdown::Both).unwrap_or(());
+        }
+        // replies that were owed on the old connection will never come.
+        self.replies.clear();
+    }
+
+    // blocks until connected again, or until the policy gives up.
+    fn reconnect(&mut self) -> Result<(), ClientError> {
+        let policy = self.policy.clone().ok_or(ClientError::Closed)?;
+        loop {
+            if policy.max_attempts().is_some_and(|max| self.attempt >= max) {
+                return Err(ClientError::Closed);
+            }
+            if let Some(at) = self.next_attempt {
+                thread::sleep(at.saturating_duration_since(Instant::now()));
+            }
+            if self.try_reconnect().is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn try_reconnect(&mut self) -> Result<(), ClientError> {
+        let policy = self.policy.clone().ok_or(ClientError::Closed)?;
+        self.attempt += 1;
+        self.next_attempt = Some(Instant::now() + policy.delay(self.attempt));
+
+        self.stream = Some(open(&self.addrs[..], self.read_timeout)?);
+        if let Err(e) = self.restore() {
+            self.disconnect();
+            return Err(e);
+        }
+        self.attempt = 0;
+        self.next_attempt = None;
+        Ok(())
+    }
+
+    // the broker forgot about us: log in again, declare the topology,
+    // subscribe again and send what was published in the meantime.
+    // channels need nothing, the broker opens them with their first frame.
+    fn restore(&mut self) -> Result<(), ClientError> {
+        match self.auth.clone() {
+            Some(Auth::Plain(user, password)) => {
+                self.control("LOGIN", plain(&user, &password).as_bytes())?
+            }
+            Some(Auth::Token(token)) => self.control("LOGIN-TOKEN", token.as_bytes())?,
+            None => {}
+        }
+        if let Some(name) = self.client_name.clone() {
+            self.control("HELLO", name.as_bytes())?;
+        }
+        for declaration in self.declarations.clone() {
+            let route = Route::new(&declaration.path, "")?;
+            let head = wire::head(
+                &self.vhost,
+                CONTROL,
+                [wire::COMMAND, declaration.kind, 0, 0],
+                "",
+                &route,
+                wire::SLICE_SIZE,
+            );
+            self.send(head, &wire::body(&route, declaration.name.as_bytes()))?;
+            // bindings are answered, one whose target is gone now is forgotten.
+            if declaration.kind == wire::NEW_BINDING
+                && self.wait_reply(CONTROL)?.status != status::OK
+            {
+                self.declarations.retain(|d| *d != declaration);
+            }
+        }
+        for (channel, route) in self.subscriptions.clone() {
+            let head = wire::head(
+                &self.vhost,
+                &channel,
+                [wire::MESSAGE, wire::SUBSCRIBE, 0, 0],
+                "",
+                &route,
+                wire::SLICE_SIZE,
+            );
+            self.send(head, &wire::body(&route, &[]))?;
+        }
+        while let Some(frame) = self.buffered.pop_front() {
+            if let Err(e) = self.write(&frame) {
+                self.buffered.push_front(frame);
+                return Err(e);
+            }
+            self.buffered_bytes -= frame.len();
+        }
+        Ok(())
+    }
+
+    fn control(&mut self, command: &str, body: &[u8]) -> Result<(), ClientError> {
+        let head = wire::head(
+            &self.vhost,
+            CONTROL,
+            [wire::NOP, 0, 0, 0],
+            command,
+            &Route::new("", "")?,
+            wire::SLICE_SIZE,
+        );
+        self.send(head, body)?;
+        match self.wait_reply(CONTROL)?.status {
+            status::OK => Ok(()),
+            code => Err(ClientError::Status(code)),
+        }
+    }
+}
+
+// a read timeout is a missing reply, not a lost connection.
+fn lost(e: &ClientError) -> bool {
+    match e {
+        ClientError::Closed => true,
+        ClientError::Status(code) => *code == status::SHUTTING_DOWN,
+        ClientError::Io(e) => !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        _ => false,
+    }
+}
+
+fn open(addrs: &[SocketAddr], read_timeout: Option<Duration>) -> Result<TcpStream, ClientError> {
+    let stream = TcpStream::connect(addrs)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(read_timeout)?;
+    Ok(stream)
+}
+
+// SASL PLAIN, as the broker expects it: authzid \0 user \0 password
+fn plain(user: &str, password: &str) -> String {
+    format!("\0{}\0{}", user, password)
+}
+
+// a connection to one virtual host of the broker. it is cheap to clone,
+// and the channels opened from it share the socket.
+#[derive(Clone)]
+pub struct Connection {
+    pub(crate) inner: Arc<Mutex<Inner>>,
+}
+
+impl Connection {
+    pub fn connect<A: ToSocketAddrs>(addr: A, vhost: &str) -> Result<Connection, ClientError> {
+        Connection::open(addr, vhost, None)
+    }
+
+    // like connect, but the connection comes back by itself when the broker goes away.
+    // requests wait for the reconnect, publishes are buffered meanwhile.
+    pub fn connect_with<A: ToSocketAddrs>(
+        addr: A,
+        vhost: &str,
+        policy: ReconnectPolicy,
+    ) -> Result<Connection, ClientError> {
+        Connection::open(addr, vhost, Some(policy))
+    }
+
+    fn open<A: ToSocketAddrs>(
+        addr: A,
+        vhost: &str,
+        policy: Option<ReconnectPolicy>,
+    ) -> Result<Connection, ClientError> {
+        wire::check_name(vhost)?;
+        // resolved once, a reconnect goes back to the same addresses.
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        let stream = open(&addrs[..], Some(READ_TIMEOUT))?;
+        Ok(Connection {
+            inner: Arc::new(Mutex::new(Inner {
+                addrs,
+                stream: Some(stream),
+                read_timeout: Some(READ_TIMEOUT),
+                vhost: vhost.to_string(),
+                replies: HashMap::new(),
+                deliveries: HashMap::new(),
+                policy,
+                auth: None,
+                client_name: None,
+                declarations: Vec::new(),
+                subscriptions: Vec::new(),
+                attempt: 0,
+                next_attempt: None,
+                buffered: VecDeque::new(),
+                buffered_bytes: 0,
+            })),
+        })
+    }
+
+    // a missing reply shows up as an io error once this has passed.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(stream) = inner.stream.as_ref() {
+            stream.set_read_timeout(timeout)?;
+        }
+        inner.read_timeout = timeout;
+        Ok(())
+    }
+
+    pub fn login(&self, user: &str, password: &str) -> Result<(), ClientError> {
+        self.control("LOGIN", plain(user, password).as_bytes())?;
+        self.inner.lock().unwrap().auth = Some(Auth::Plain(user.to_string(), password.to_string()));
+        Ok(())
+    }
+
+    pub fn login_token(&self, token: &str) -> Result<(), ClientError> {
+        self.control("LOGIN-TOKEN", token.as_bytes())?;
+        self.inner.lock().unwrap().auth = Some(Auth::Token(token.to_string()));
+        Ok(())
+    }
+
+    // the name shows up in the broker's connection registry.
+    pub fn set_client_name(&self, name: &str) -> Result<(), ClientError> {
+        self.control("HELLO", name.as_bytes())?;
+        self.inner.lock().unwrap().client_name = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn channel(&self, name: &str) -> Result<Channel, ClientError> {
+        if name.is_empty() || wire::check_name(name).is_err() {
+            return Err(ClientError::InvalidName(name.to_string()));
+        }
+        Ok(Channel::new(self.clone(), name.to_string()))
+    }
+
+    // false while a reconnect is pending.
+    pub fn connected(&self) -> bool {
+        self.inner.lock().unwrap().stream.is_some()
+    }
+
+    // bytes of publishes waiting for the connection to come back.
+    pub fn buffered(&self) -> usize {
+        self.inner.lock().unwrap().buffered_bytes
+    }
+
+    pub fn close(self) -> Result<(), ClientError> {
+        let mut inner = self.inner.lock().unwrap();
+        // closing on purpose, nothing is reconnected after this.
+        inner.policy = None;
+        if let Some(stream) = inner.stream.take() {
+            stream.shutdown(Shutdown::Both)?;
+        }
+        Ok(())
+    }
+
+    fn control(&self, command: &str, body: &[u8]) -> Result<(), ClientError> {
+        self.inner
+            .lock()
+            .unwrap()
+            .request(|inner| inner.control(command, body))
+    }
+}
@@ -0,0 +1,45 @@
+use msg_queue::mq::protocol::status;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    // a status code other than OK in the msg_sign field of a reply.
+    Status(u16),
+    // exchange, queue and channel names have to fit into 32 bytes.
+    InvalidName(String),
+    // the broker closed the connection.
+    Closed,
+    // disconnected, and the publish buffer of the reconnect policy is full.
+    BufferFull,
+    // a typed body could not be encoded or decoded.
+    Codec(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "io error: {}", e),
+            ClientError::Status(code) => write!(
+                f,
+                "broker replied with status 0x{:x} ({})",
+                code,
+                status::name(*code)
+            ),
+            ClientError::InvalidName(name) => write!(f, "invalid name: {}", name),
+            ClientError::Closed => write!(f, "connection closed by the broker"),
+            ClientError::BufferFull => write!(f, "disconnected and the publish buffer is full"),
+            ClientError::Codec(reason) => write!(f, "codec error: {}", reason),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
@@ -0,0 +1,15 @@
+// a blocking client for the broker. frames are built from the broker's own protocol types,
+// so both sides agree on the layout of a DataHead.
+pub mod channel;
+pub mod cli;
+pub mod codec;
+pub mod connection;
+pub mod error;
+pub mod reconnect;
+pub mod wire;
+
+pub use channel::{Binding, Channel};
+pub use connection::Connection;
+pub use error::ClientError;
+pub use reconnect::ReconnectPolicy;
+pub use wire::Route;
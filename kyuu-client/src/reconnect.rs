@@ -0,0 +1,77 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+// how a connection gets back to the broker after it went away.
+// without a policy, a lost connection is simply an error.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+    // encoded bytes of publishes held back while disconnected.
+    publish_buffer: usize,
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+            publish_buffer: 1024 * 1024,
+        }
+    }
+
+    pub fn set_initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    pub fn set_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    // None retries forever.
+    pub fn set_max_attempts(mut self, attempts: Option<u32>) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    pub fn set_publish_buffer(mut self, bytes: usize) -> Self {
+        self.publish_buffer = bytes;
+        self
+    }
+
+    pub fn max_attempts(&self) -> Option<u32> {
+        self.max_attempts
+    }
+
+    pub fn publish_buffer(&self) -> usize {
+        self.publish_buffer
+    }
+
+    // doubles with every attempt up to max_delay. half of it is random,
+    // so clients that lost the same broker do not all come back at once.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let delay = exp.min(self.max_delay);
+        let half = delay / 2;
+        let jitter = random() % (half.as_nanos() as u64 + 1);
+        half + Duration::from_nanos(jitter)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy::new()
+    }
+}
+
+// good enough for jitter, and saves a dependency on rand.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
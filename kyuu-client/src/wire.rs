@@ -0,0 +1,262 @@
+use crate::error::ClientError;
+use msg_queue::mq::protocol::content;
+use msg_queue::mq::protocol::proto::DataHead;
+use msg_queue::mq::protocol::protobase::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{ErrorKind, Read, Write};
+
+pub const HEAD_SIZE: usize = 256;
+pub const SLICE_SIZE: u32 = 256;
+// bodies above BULK_THRESHOLD go out in bigger slices, the broker honors the slice size.
+pub const BULK_SLICE_SIZE: u32 = 4096;
+const BULK_THRESHOLD: usize = 64 * 1024;
+// a reply announcing more than this is treated as a broken stream.
+const MAX_REPLY_SIZE: u64 = 256 * 1024 * 1024;
+
+// routing_mod[0]
+pub const MESSAGE: u8 = 0;
+pub const COMMAND: u8 = 1;
+pub const NOP: u8 = 0xff;
+
+// routing_mod[1] of a message
+pub const PUSH: u8 = 0;
+pub const FETCH: u8 = 1;
+pub const SUBSCRIBE: u8 = 2;
+pub const CANCEL: u8 = 3;
+
+// routing_mod[1] of a command
+pub const NEW_QUEUE: u8 = 0;
+pub const NEW_EXCHANGE: u8 = 1;
+pub const NEW_BINDING: u8 = 2;
+pub const DROP_QUEUE: u8 = 3;
+pub const DROP_EXCHANGE: u8 = 4;
+pub const DROP_BINDING: u8 = 5;
+pub const LIST_BINDINGS: u8 = 6;
+
+// routing_mod[2]
+pub const DIRECT: u8 = 0;
+pub const TOPIC: u8 = 1;
+pub const FANOUT: u8 = 2;
+pub const HEADERS: u8 = 3;
+
+// where a frame goes: the exchanges from the root down, and the queue in the last slot.
+// up to two exchanges fit into the route slots of the head, a longer path is sent
+// as a line in front of the body.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Route {
+    exchanges: Vec<String>,
+    queue: String,
+    kind: u8,
+}
+
+impl Route {
+    // path is the exchange path separated by '/', e.g. "orders/eu".
+    pub fn new(path: &str, queue: &str) -> Result<Route, ClientError> {
+        Route::with_kind(DIRECT, path, queue)
+    }
+
+    // in a topic pattern '*' matches exactly one exchange and '#' any number of them,
+    // e.g. "orders/*" or "#". a queue of "*" matches every queue of the matched exchanges.
+    pub fn topic(pattern: &str, queue: &str) -> Result<Route, ClientError> {
+        Route::with_kind(TOPIC, pattern, queue)
+    }
+
+    // every queue of the exchange at path, and of the exchanges below it with descendants.
+    pub fn fanout(path: &str, descendants: bool) -> Result<Route, ClientError> {
+        Route::with_kind(FANOUT, path, if descendants { "#" } else { "" })
+    }
+
+    // the queues bound to the exchange at path with a match on the headers of the message.
+    pub fn headers(path: &str) -> Result<Route, ClientError> {
+        Route::with_kind(HEADERS, path, "")
+    }
+
+    fn with_kind(kind: u8, path: &str, queue: &str) -> Result<Route, ClientError> {
+        let exchanges = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|name| check_name(name).map(|name| name.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Route {
+            exchanges,
+            queue: check_name(queue)?.to_string(),
+            kind,
+        })
+    }
+
+    pub fn path(&self) -> String {
+        self.exchanges.join("/")
+    }
+
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+
+    pub fn kind(&self) -> u8 {
+        self.kind
+    }
+
+    // whether the path goes in front of the body, see body().
+    pub fn long(&self) -> bool {
+        self.exchanges.len() > 2
+    }
+
+    fn slot(&self, i: usize) -> [u8; 32] {
+        match i {
+            // the third slot is not walked by the broker.
+            2 => [0u8; 32],
+            3 => fixed(&self.queue),
+            _ => fixed(self.exchanges.get(i).map_or("", |name| name.as_str())),
+        }
+    }
+}
+
+pub fn check_name(name: &str) -> Result<&str, ClientError> {
+    if name.len() > 32 || name.contains(['\0', '\n']) {
+        return Err(ClientError::InvalidName(name.to_string()));
+    }
+    Ok(name)
+}
+
+// exchange paths and binding keys are sent separated by spaces.
+pub fn check_word(word: &str) -> Result<&str, ClientError> {
+    if word.is_empty() || word.contains(char::is_whitespace) || word.contains('\0') {
+        return Err(ClientError::InvalidName(word.to_string()));
+    }
+    Ok(word)
+}
+
+// the body of a frame to route, with the exchange path in front of it for a long route.
+pub fn body<'a>(route: &Route, body: &'a [u8]) -> Cow<'a, [u8]> {
+    if !route.long() {
+        return Cow::Borrowed(body);
+    }
+    let mut out = format!("{}\n", route.path()).into_bytes();
+    out.extend_from_slice(body);
+    Cow::Owned(out)
+}
+
+// headers go in front of the body, one "key=value" line each and an empty line after them.
+pub fn with_headers(headers: &[(&str, &str)], body: &[u8]) -> Result<Vec<u8>, ClientError> {
+    let mut out = Vec::new();
+    for (key, value) in headers {
+        if key.is_empty() || key.contains(['=', '\n']) || value.contains('\n') {
+            return Err(ClientError::InvalidName(format!("{}={}", key, value)));
+        }
+        out.extend_from_slice(format!("{}={}\n", key, value).as_bytes());
+    }
+    out.push(b'\n');
+    out.extend_from_slice(body);
+    Ok(out)
+}
+
+pub fn fixed<const N: usize>(s: &str) -> [u8; N] {
+    let mut out = [0u8; N];
+    let bytes = &s.as_bytes()[..s.len().min(N)];
+    out[..bytes.len()].copy_from_slice(bytes);
+    out
+}
+
+pub fn trimmed(raw: &[u8]) -> String {
+    String::from_utf8_lossy(raw)
+        .trim_end_matches("\0")
+        .to_string()
+}
+
+pub fn slice_size_for(body: &[u8]) -> u32 {
+    if body.len() > BULK_THRESHOLD {
+        BULK_SLICE_SIZE
+    } else {
+        SLICE_SIZE
+    }
+}
+
+// routing_mod[2] and [3] are taken from the route.
+pub fn head(
+    vhost: &str,
+    channel: &str,
+    mut routing_mod: [u8; 4],
+    command: &str,
+    route: &Route,
+    slice_size: u32,
+) -> DataHead {
+    routing_mod[2] = route.kind;
+    routing_mod[3] = route.long() as u8;
+    DataHead {
+        virtual_host: fixed(vhost),
+        channel: fixed(channel),
+        version: [1u8, 0u8, 0u8, 0u8],
+        routing_mod,
+        command: fixed(command),
+        route0: route.slot(0),
+        route1: route.slot(1),
+        route2: route.slot(2),
+        route3: route.slot(3),
+        slice_count: 0,
+        slice_size,
+        count: 0,
+        msg_sign: 0,
+        ack: 0,
+        content_type: content::UNSPECIFIED,
+        reserved: [0u8; 15],
+    }
+}
+
+// the body is padded to whole slices behind the head.
+pub fn encode(mut head: DataHead, body: &[u8]) -> Vec<u8> {
+    let slice_size = head.slice_size.max(1) as usize;
+    let len = body.len();
+    let mut body = body.to_vec();
+    if !body.len().is_multiple_of(slice_size) {
+        body.resize(body.len() + slice_size - body.len() % slice_size, 0u8);
+    }
+    head.count = len as u32;
+    head.slice_count = (body.len() / slice_size) as u32;
+
+    let mut out = head.serialize_vec();
+    out.append(&mut body);
+    out
+}
+
+pub fn write_frame<W: Write>(
+    stream: &mut W,
+    head: DataHead,
+    body: &[u8],
+) -> Result<(), ClientError> {
+    stream.write_all(&encode(head, body))?;
+    Ok(())
+}
+
+pub fn read_frame<R: Read>(stream: &mut R) -> Result<(DataHead, Vec<u8>), ClientError> {
+    let mut raw = [0u8; HEAD_SIZE];
+    read_exact(stream, &mut raw)?;
+    let head = DataHead::deserialize(raw);
+
+    let size = u64::from(head.slice_count) * u64::from(head.slice_size);
+    if size > MAX_REPLY_SIZE {
+        return Err(ClientError::Closed);
+    }
+    let mut body = vec![0u8; size as usize];
+    read_exact(stream, &mut body)?;
+    Ok((head, body))
+}
+
+fn read_exact<R: Read>(stream: &mut R, buf: &mut [u8]) -> Result<(), ClientError> {
+    stream.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => ClientError::Closed,
+        _ => ClientError::Io(e),
+    })
+}
+
+// the broker pads bodies with \0 up to whole slices, the padding is cut off again here.
+// count holds the length before padding, older brokers leave it at 0.
+pub fn unpad(head: &DataHead, mut body: Vec<u8>) -> Vec<u8> {
+    if head.count > 0 && head.count as usize <= body.len() {
+        body.truncate(head.count as usize);
+        return body;
+    }
+    while body.last() == Some(&0u8) {
+        body.pop();
+    }
+    body
+}
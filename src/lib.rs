@@ -0,0 +1,2 @@
+// the broker as a library, so the client and the tools share its protocol types.
+pub mod mq;
@@ -1,20 +1,56 @@
-use crate::mq::breaker;
-use crate::mq::common::context::RuntimeContext;
 use inio::io::reader;
+use msg_queue::mq::auth::user::User;
+use msg_queue::mq::breaker::core::Core;
+use msg_queue::mq::breaker::shutdown::Shutdown;
+use msg_queue::mq::common::context::RuntimeContext;
+use msg_queue::mq::common::sync::SafeLock;
+use msg_queue::mq::net::acl::NetworkAcl;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::env;
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use std::{io, thread};
+use std::time::Duration;
+use std::{io, process, thread};
 
 // always remember that the last value of RoutingKey is the name of the Queue.
 // note that the \0 at the end of the strings must be trimmed using trim_end_matches() !!
-pub mod mq;
 pub mod test;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let ctx = ini_loader()?; // Arc::new(Mutex::new(construct_ctx()?));
-    let rt_handle = set_runtime(Arc::from(Mutex::from(ctx)));
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 4 && args[1] == "hash-password" {
+        // prints an entry for the users file: msg-queue hash-password <user> <password>
+        println!(
+            "{}",
+            User::new(args[2].clone(), args[3].as_bytes())?.to_line()
+        );
+        return Ok(());
+    }
+
+    let ctx = Arc::from(Mutex::from(ini_loader()?)); // Arc::new(Mutex::new(construct_ctx()?));
+    let core = Core::new(ctx.clone());
+    watch_signals(core.shutdown_handle())?;
+    let rt_handle = set_runtime(ctx, core);
     rt_handle.join().unwrap();
+    println!("[mq] shut down");
+    Ok(())
+}
+
+// the first SIGINT/SIGTERM drains the broker, a second one exits right away.
+fn watch_signals(shutdown: Arc<Shutdown>) -> Result<(), Box<dyn Error>> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if shutdown.requested() {
+                println!("[mq] received signal {} again, exiting", signal);
+                process::exit(1);
+            }
+            println!("[mq] received signal {}, shutting down", signal);
+            shutdown.request();
+        }
+    });
     Ok(())
 }
 
@@ -23,6 +59,10 @@ fn ini_loader() -> Result<RuntimeContext, Box<dyn Error>> {
     let host: String;
     let port: u16;
     let virtual_host_counts: usize;
+    let workers: Option<usize>;
+    let async_runtime: bool;
+    let shutdown_timeout: Option<u64>;
+    let acl: NetworkAcl;
 
     if let Some(sec) = conf.get_section("Net") {
         host = if let Some(key) = sec.get_key("Host") {
@@ -42,16 +82,57 @@ fn ini_loader() -> Result<RuntimeContext, Box<dyn Error>> {
         } else {
             1
         };
+
+        workers = if let Some(key) = sec.get_key("Workers") {
+            Some(key.value.parse()?)
+        } else {
+            None
+        };
+
+        async_runtime = if let Some(key) = sec.get_key("Runtime") {
+            match key.value.to_lowercase().as_str() {
+                "tokio" => true,
+                "reactor" => false,
+                other => panic!("[mq] unknown runtime \"{}\"", other),
+            }
+        } else {
+            false
+        };
+
+        // seconds
+        shutdown_timeout = if let Some(key) = sec.get_key("ShutdownTimeout") {
+            Some(key.value.parse()?)
+        } else {
+            None
+        };
+
+        acl = NetworkAcl::parse(
+            sec.get_key("Allow").map(|key| key.value.as_str()),
+            sec.get_key("Deny").map(|key| key.value.as_str()),
+        )?;
     } else {
         panic!("[mq] config file not found");
     };
 
     let mut ctx = RuntimeContext::new(host, port);
+    ctx.acl = acl;
+    ctx.async_runtime = async_runtime;
+    if let Some(workers) = workers {
+        ctx.workers = workers;
+    }
+    if let Some(secs) = shutdown_timeout {
+        ctx.shutdown_timeout = Duration::from_secs(secs);
+    }
 
     for i in 0..virtual_host_counts {
         if let Some(sec) = conf.get_section(&format!("Host{}", i)) {
             if let Some(key) = sec.get_key("name") {
                 ctx.hosts.push(key.value.clone());
+                let host_acl = NetworkAcl::parse(
+                    sec.get_key("Allow").map(|key| key.value.as_str()),
+                    sec.get_key("Deny").map(|key| key.value.as_str()),
+                )?;
+                ctx.host_acls.insert(key.value.clone(), host_acl);
             } else {
                 panic!("[mq] config key \"name\" of host {} not found", i);
             }
@@ -60,6 +141,30 @@ fn ini_loader() -> Result<RuntimeContext, Box<dyn Error>> {
         }
     }
 
+    if let Some(sec) = conf.get_section("Limits") {
+        if let Some(key) = sec.get_key("MaxMessageSize") {
+            ctx.limits.max_message_size = key.value.parse()?;
+        }
+        if let Some(key) = sec.get_key("MaxChannels") {
+            ctx.limits.max_channels = key.value.parse()?;
+        }
+        if let Some(key) = sec.get_key("MaxBufferedBytes") {
+            ctx.limits.max_buffered_bytes = key.value.parse()?;
+        }
+    }
+
+    if let Some(sec) = conf.get_section("Auth") {
+        if let Some(key) = sec.get_key("UsersFile") {
+            ctx.users_file = Some(key.value.clone());
+        }
+        if let Some(key) = sec.get_key("PermissionsFile") {
+            ctx.permissions_file = Some(key.value.clone());
+        }
+        if let Some(key) = sec.get_key("TokenKey") {
+            ctx.token_key = Some(key.value.clone());
+        }
+    }
+
     Ok(ctx)
 }
 
@@ -80,13 +185,40 @@ fn construct_ctx() -> Result<RuntimeContext, Box<dyn Error>> {
     Ok(RuntimeContext::new(host, port))
 }
 
-fn set_runtime(ctx: Arc<Mutex<RuntimeContext>>) -> JoinHandle<()> {
+fn set_runtime(ctx: Arc<Mutex<RuntimeContext>>, core: Core) -> JoinHandle<()> {
     thread::spawn(move || {
-        run(ctx);
+        run(ctx, core);
     })
 }
 
-fn run(ctx: Arc<Mutex<RuntimeContext>>) {
-    let mut core = breaker::core::Core::new(ctx);
+fn run(ctx: Arc<Mutex<RuntimeContext>>, mut core: Core) {
+    let (async_runtime, workers, shutdown_timeout) = {
+        let ctx = ctx.safe_lock();
+        (ctx.async_runtime, ctx.workers, ctx.shutdown_timeout)
+    };
+    if async_runtime {
+        run_async(core, workers, shutdown_timeout);
+    } else {
+        core.start();
+    }
+}
+
+#[cfg(feature = "tokio")]
+fn run_async(core: Core, workers: usize, shutdown_timeout: Duration) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(workers)
+        .enable_all()
+        .build()
+        .unwrap();
+    if let Err(e) = runtime.block_on(core.serve()) {
+        println!("[mq] runtime stopped: {}", e);
+    }
+    // blocking tasks still handling a frame are not waited for past the deadline.
+    runtime.shutdown_timeout(shutdown_timeout);
+}
+
+#[cfg(not(feature = "tokio"))]
+fn run_async(mut core: Core, _workers: usize, _shutdown_timeout: Duration) {
+    println!("[mq] built without the \"tokio\" feature, falling back to the reactor");
     core.start();
 }
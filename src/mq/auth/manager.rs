@@ -0,0 +1,102 @@
+use crate::mq::auth::perm::{Permission, PermissionRule, PermissionStore};
+use crate::mq::auth::sasl::PlainCredentials;
+use crate::mq::auth::token::TokenVerifier;
+use crate::mq::auth::user::UserStore;
+use crate::mq::protocol::status;
+use std::time::SystemTime;
+
+pub struct Principal {
+    pub user: String,
+    // None when no permissions file is configured: the user may do anything.
+    rules: Option<Vec<PermissionRule>>,
+    // set for token logins, the connection is closed once it has passed.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Principal {
+    pub fn new(user: String, rules: Option<Vec<PermissionRule>>) -> Principal {
+        Principal {
+            user,
+            rules,
+            expires_at: None,
+        }
+    }
+
+    pub fn set_expiry(mut self, expires_at: SystemTime) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= SystemTime::now())
+    }
+
+    pub fn authorize(&self, vhost: &str, permission: Permission, path: &str) -> bool {
+        match &self.rules {
+            Some(rules) => rules
+                .iter()
+                .any(|rule| rule.allows(vhost, permission, path)),
+            None => true,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AuthManager {
+    users: Option<UserStore>,
+    permissions: Option<PermissionStore>,
+    token_verifier: Option<TokenVerifier>,
+}
+
+impl AuthManager {
+    pub fn new() -> AuthManager {
+        AuthManager::default()
+    }
+
+    pub fn set_users(mut self, users: UserStore) -> Self {
+        self.users = Some(users);
+        self
+    }
+
+    pub fn set_permissions(mut self, permissions: PermissionStore) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
+    pub fn set_token_key(mut self, key: Vec<u8>) -> Self {
+        self.token_verifier = Some(TokenVerifier::new(key));
+        self
+    }
+
+    // with neither a users file nor a token key configured, every connection is anonymous.
+    pub fn enabled(&self) -> bool {
+        self.users.is_some() || self.token_verifier.is_some()
+    }
+
+    pub fn login_plain(&self, data: &[u8]) -> Result<Principal, u16> {
+        let users = self.users.as_ref().ok_or(status::AUTH_FAILED)?;
+        let credentials = PlainCredentials::parse(data).ok_or(status::AUTH_FAILED)?;
+        if users.authenticate(&credentials.username, &credentials.password) {
+            let rules = self
+                .permissions
+                .as_ref()
+                .map(|p| p.get(&credentials.username));
+            Ok(Principal::new(credentials.username, rules))
+        } else {
+            Err(status::AUTH_FAILED)
+        }
+    }
+
+    pub fn login_token(&self, data: &[u8]) -> Result<Principal, u16> {
+        let verifier = self.token_verifier.as_ref().ok_or(status::AUTH_FAILED)?;
+        let token = String::from_utf8_lossy(data)
+            .trim_end_matches("\0")
+            .to_string();
+        let claims = verifier.verify(&token)?;
+        Ok(
+            Principal::new(claims.sub.clone(), Some(claims.rules()))
+                .set_expiry(claims.expires_at()),
+        )
+    }
+}
@@ -0,0 +1,5 @@
+pub mod manager;
+pub mod perm;
+pub mod sasl;
+pub mod token;
+pub mod user;
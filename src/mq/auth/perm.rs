@@ -0,0 +1,285 @@
+use crate::mq::protocol::raw::{Raw, RawCommand, RawData, RawMessage};
+use crate::mq::routing::binding::{BindingKind, BindingSpec};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    Configure,
+    Write,
+    Read,
+}
+
+impl Permission {
+    // the permissions a frame needs, each with the resource path it acts on: /exchange/.../object
+    // a frame is only let through if every one of them is granted.
+    pub fn required(raw: &RawData) -> Vec<(Permission, String)> {
+        let mut path = raw.routing_key.exchange_path();
+        let mut required = vec![];
+        let permission = match &raw.raw {
+            Raw::Command(cmd) => {
+                let name = match cmd {
+                    RawCommand::NewQueue(data)
+                    | RawCommand::NewExchange(data)
+                    | RawCommand::DropQueue(data)
+                    | RawCommand::DropExchange(data) => Some(
+                        String::from_utf8_lossy(data)
+                            .trim_end_matches("\0")
+                            .trim()
+                            .to_string(),
+                    ),
+                    // bindings are configured on the exchange they are made on, and on the
+                    // target. a bound queue gets the messages of the exchange written to it.
+                    RawCommand::NewBinding(data) | RawCommand::DropBinding(data) => {
+                        if let Some(spec) = BindingSpec::parse(data) {
+                            let target = format!("/{}", spec.path);
+                            if spec.kind == BindingKind::Queue {
+                                required.push((Permission::Write, target.clone()));
+                            }
+                            required.push((Permission::Configure, target));
+                        }
+                        None
+                    }
+                    RawCommand::ListBindings => None,
+                    RawCommand::Nop => return vec![],
+                };
+                path.extend(name);
+                Permission::Configure
+            }
+            Raw::Message(msg) => {
+                path.push(raw.routing_key.queue().clone());
+                match msg {
+                    RawMessage::Push(_) => Permission::Write,
+                    RawMessage::Fetch(_) | RawMessage::Subscribe(_) | RawMessage::Cancel(_) => {
+                        Permission::Read
+                    }
+                    RawMessage::Nop => return vec![],
+                }
+            }
+            Raw::Nop => return vec![],
+        };
+        required.insert(0, (permission, format!("/{}", path.join("/"))));
+        required
+    }
+}
+
+// one line of the permissions file: user vhost configure write read
+// each of the last three columns is a path prefix ("/" grants everything), or "-" to grant nothing.
+#[derive(Debug, Clone)]
+pub struct PermissionRule {
+    pub vhost: String,
+    configure: Option<String>,
+    write: Option<String>,
+    read: Option<String>,
+}
+
+impl PermissionRule {
+    pub fn new(
+        vhost: String,
+        configure: Option<String>,
+        write: Option<String>,
+        read: Option<String>,
+    ) -> PermissionRule {
+        PermissionRule {
+            vhost,
+            configure,
+            write,
+            read,
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<(String, PermissionRule)> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 5 {
+            return None;
+        }
+        let prefix = |s: &str| -> Option<Option<String>> {
+            match s {
+                "-" => Some(None),
+                _ if s.starts_with('/') => Some(Some(s.to_string())),
+                _ => None,
+            }
+        };
+        Some((
+            parts[0].to_string(),
+            PermissionRule::new(
+                parts[1].to_string(),
+                prefix(parts[2])?,
+                prefix(parts[3])?,
+                prefix(parts[4])?,
+            ),
+        ))
+    }
+
+    pub fn allows(&self, vhost: &str, permission: Permission, path: &str) -> bool {
+        if self.vhost != vhost {
+            return false;
+        }
+        let prefix = match permission {
+            Permission::Configure => &self.configure,
+            Permission::Write => &self.write,
+            Permission::Read => &self.read,
+        };
+        match prefix {
+            // prefixes match whole segments: /orders covers /orders/eu but not /orders-old.
+            // a trailing '/' changes nothing, only "/" itself covers everything.
+            Some(prefix) => {
+                let prefix = prefix.trim_end_matches('/');
+                prefix.is_empty()
+                    || path == prefix
+                    || (path.starts_with(prefix)
+                        && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PermissionStore {
+    rules: HashMap<String, Vec<PermissionRule>>,
+}
+
+impl PermissionStore {
+    pub fn new() -> PermissionStore {
+        PermissionStore::default()
+    }
+
+    pub fn load(path: &str) -> Result<PermissionStore, Box<dyn Error>> {
+        let mut store = PermissionStore::new();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match PermissionRule::parse(line) {
+                Some((user, rule)) => store.add(user, rule),
+                None => println!("[mq] malformed entry in permissions file at line {}", i + 1),
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn add(&mut self, user: String, rule: PermissionRule) {
+        self.rules.entry(user).or_default().push(rule);
+    }
+
+    pub fn get(&self, user: &str) -> Vec<PermissionRule> {
+        self.rules.get(user).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mq::protocol::raw::IOType;
+    use crate::mq::routing::key::{RoutingKey, RoutingPath};
+
+    fn raw(raw: Raw, exchanges: &[&str], queue: &str) -> RawData {
+        let path = RoutingPath::new(
+            exchanges.iter().map(|x| x.to_string()).collect(),
+            queue.to_string(),
+        );
+        RawData {
+            raw,
+            channel: String::new(),
+            virtual_host: "vh".to_string(),
+            routing_key: RoutingKey::Direct(path),
+            io_type: IOType::Write,
+            content_type: 0,
+            peer_addr: None,
+            connection: None,
+        }
+    }
+
+    fn rule(line: &str) -> PermissionRule {
+        PermissionRule::parse(line).unwrap().1
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments() {
+        let rule = rule("alice vh /orders /orders/eu/ /");
+        assert!(rule.allows("vh", Permission::Configure, "/orders"));
+        assert!(rule.allows("vh", Permission::Configure, "/orders/eu/q"));
+        assert!(!rule.allows("vh", Permission::Configure, "/orders-old"));
+        assert!(!rule.allows("vh", Permission::Configure, "/"));
+        assert!(rule.allows("vh", Permission::Write, "/orders/eu/q"));
+        assert!(!rule.allows("vh", Permission::Write, "/orders/us/q"));
+        assert!(rule.allows("vh", Permission::Read, "/anything/at/all"));
+        assert!(!rule.allows("other", Permission::Read, "/anything"));
+    }
+
+    #[test]
+    fn dash_grants_nothing() {
+        let rule = rule("alice vh - - /logs");
+        assert!(!rule.allows("vh", Permission::Configure, "/logs"));
+        assert!(!rule.allows("vh", Permission::Write, "/logs/q"));
+        assert!(rule.allows("vh", Permission::Read, "/logs/q"));
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert!(PermissionRule::parse("alice vh / /").is_none());
+        assert!(PermissionRule::parse("alice vh orders / /").is_none());
+        assert!(PermissionRule::parse("alice vh / / / /").is_none());
+    }
+
+    #[test]
+    fn messages_act_on_the_queue() {
+        let push = raw(Raw::Message(RawMessage::Push(vec![])), &["a", "b"], "q");
+        assert_eq!(
+            Permission::required(&push),
+            vec![(Permission::Write, "/a/b/q".to_string())]
+        );
+        let fetch = raw(Raw::Message(RawMessage::Fetch(vec![])), &["a"], "q");
+        assert_eq!(
+            Permission::required(&fetch),
+            vec![(Permission::Read, "/a/q".to_string())]
+        );
+    }
+
+    #[test]
+    fn commands_act_on_the_object_they_name() {
+        let new_queue = raw(
+            Raw::Command(RawCommand::NewQueue(b"q\0\0".to_vec())),
+            &["a"],
+            "",
+        );
+        assert_eq!(
+            Permission::required(&new_queue),
+            vec![(Permission::Configure, "/a/q".to_string())]
+        );
+        let nop = raw(Raw::Command(RawCommand::Nop), &["a"], "");
+        assert!(Permission::required(&nop).is_empty());
+    }
+
+    #[test]
+    fn bindings_act_on_the_exchange_and_the_target() {
+        let bind_queue = raw(
+            Raw::Command(RawCommand::NewBinding(b"queue b/q key".to_vec())),
+            &["a"],
+            "",
+        );
+        assert_eq!(
+            Permission::required(&bind_queue),
+            vec![
+                (Permission::Configure, "/a".to_string()),
+                (Permission::Write, "/b/q".to_string()),
+                (Permission::Configure, "/b/q".to_string()),
+            ]
+        );
+        let unbind_exchange = raw(
+            Raw::Command(RawCommand::DropBinding(b"exchange b/c #".to_vec())),
+            &["a"],
+            "",
+        );
+        assert_eq!(
+            Permission::required(&unbind_exchange),
+            vec![
+                (Permission::Configure, "/a".to_string()),
+                (Permission::Configure, "/b/c".to_string()),
+            ]
+        );
+    }
+}
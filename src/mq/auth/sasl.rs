@@ -0,0 +1,56 @@
+// SASL PLAIN message: [authzid] \0 authcid \0 passwd
+// the body is padded with \0 up to the slice size, so the trailing \0s are trimmed from passwd.
+pub struct PlainCredentials {
+    pub authzid: String,
+    pub username: String,
+    pub password: Vec<u8>,
+}
+
+impl PlainCredentials {
+    pub fn parse(data: &[u8]) -> Option<PlainCredentials> {
+        let mut parts = data.splitn(3, |b| *b == 0u8);
+        let authzid = String::from_utf8(parts.next()?.to_vec()).ok()?;
+        let username = String::from_utf8(parts.next()?.to_vec()).ok()?;
+        let mut password = parts.next()?.to_vec();
+        while password.last() == Some(&0u8) {
+            password.pop();
+        }
+
+        if username.is_empty() || (!authzid.is_empty() && authzid != username) {
+            // acting on behalf of another user is not supported.
+            return None;
+        }
+
+        Some(PlainCredentials {
+            authzid,
+            username,
+            password,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_credentials() {
+        let credentials = PlainCredentials::parse(b"\0alice\0secret").unwrap();
+        assert_eq!(credentials.authzid, "");
+        assert_eq!(credentials.username, "alice");
+        assert_eq!(credentials.password, b"secret");
+    }
+
+    #[test]
+    fn trims_the_slice_padding() {
+        let credentials = PlainCredentials::parse(b"alice\0alice\0secret\0\0\0\0").unwrap();
+        assert_eq!(credentials.password, b"secret");
+    }
+
+    #[test]
+    fn rejects_other_identities_and_missing_parts() {
+        assert!(PlainCredentials::parse(b"bob\0alice\0secret").is_none());
+        assert!(PlainCredentials::parse(b"\0\0secret").is_none());
+        assert!(PlainCredentials::parse(b"\0alice").is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::mq::auth::perm::PermissionRule;
+use crate::mq::protocol::status;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+}
+
+// claims of a HS256 token: {"sub": "batch", "vhosts": ["vh"], "scopes": ["read", "write:/orders"], "exp": 1700000000}
+// a scope is configure, write or read, optionally narrowed to a path prefix.
+#[derive(Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    #[serde(default)]
+    pub vhosts: Vec<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub exp: u64,
+}
+
+impl Claims {
+    pub fn expires_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.exp)
+    }
+
+    pub fn rules(&self) -> Vec<PermissionRule> {
+        let mut rules = vec![];
+        for vhost in &self.vhosts {
+            for scope in &self.scopes {
+                let (name, prefix) = match scope.split_once(':') {
+                    Some((name, prefix)) if prefix.starts_with('/') => (name, prefix),
+                    Some(_) => continue,
+                    None => (scope.as_str(), "/"),
+                };
+                let prefix = Some(prefix.to_string());
+                let rule = match name {
+                    "configure" => PermissionRule::new(vhost.clone(), prefix, None, None),
+                    "write" => PermissionRule::new(vhost.clone(), None, prefix, None),
+                    "read" => PermissionRule::new(vhost.clone(), None, None, prefix),
+                    _ => continue,
+                };
+                rules.push(rule);
+            }
+        }
+        rules
+    }
+}
+
+pub struct TokenVerifier {
+    key: Vec<u8>,
+}
+
+impl TokenVerifier {
+    pub fn new(key: Vec<u8>) -> TokenVerifier {
+        TokenVerifier { key }
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, u16> {
+        let mut parts = token.trim().split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(p), Some(s)) if parts.next().is_none() => (h, p, s),
+            _ => return Err(status::AUTH_FAILED),
+        };
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).map_err(|_| status::AUTH_FAILED)?;
+        mac.update(header.as_bytes());
+        mac.update(b".");
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| status::AUTH_FAILED)?;
+        // verify_slice compares in constant time.
+        mac.verify_slice(&signature)
+            .map_err(|_| status::AUTH_FAILED)?;
+
+        let header: Header = Self::decode_part(header)?;
+        if header.alg != "HS256" {
+            return Err(status::AUTH_FAILED);
+        }
+        let claims: Claims = Self::decode_part(payload)?;
+        if claims.expires_at() <= SystemTime::now() {
+            return Err(status::TOKEN_EXPIRED);
+        }
+        Ok(claims)
+    }
+
+    fn decode_part<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, u16> {
+        let json = URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|_| status::AUTH_FAILED)?;
+        serde_json::from_slice(&json).map_err(|_| status::AUTH_FAILED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mq::auth::perm::Permission;
+
+    const KEY: &[u8] = b"test-key";
+
+    fn sign(key: &[u8], header: &str, claims: &str) -> String {
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header),
+            URL_SAFE_NO_PAD.encode(claims)
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(signed.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}", signed, signature)
+    }
+
+    fn token(key: &[u8], exp: u64) -> String {
+        let claims = format!(
+            r#"{{"sub":"batch","vhosts":["vh"],"scopes":["read","write:/orders"],"exp":{}}}"#,
+            exp
+        );
+        sign(key, r#"{"alg":"HS256","typ":"JWT"}"#, &claims)
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn verifies_a_signed_token() {
+        let claims = TokenVerifier::new(KEY.to_vec())
+            .verify(&token(KEY, now() + 60))
+            .unwrap();
+        assert_eq!(claims.sub, "batch");
+
+        let rules = claims.rules();
+        assert_eq!(rules.len(), 2);
+        assert!(rules
+            .iter()
+            .any(|rule| rule.allows("vh", Permission::Read, "/any/q")));
+        assert!(rules
+            .iter()
+            .any(|rule| rule.allows("vh", Permission::Write, "/orders/q")));
+        assert!(!rules
+            .iter()
+            .any(|rule| rule.allows("vh", Permission::Write, "/logs/q")));
+    }
+
+    #[test]
+    fn rejects_an_expired_token() {
+        let result = TokenVerifier::new(KEY.to_vec()).verify(&token(KEY, now() - 1));
+        assert_eq!(result.err(), Some(status::TOKEN_EXPIRED));
+    }
+
+    #[test]
+    fn rejects_a_bad_signature() {
+        let verifier = TokenVerifier::new(KEY.to_vec());
+        let result = verifier.verify(&token(b"other-key", now() + 60));
+        assert_eq!(result.err(), Some(status::AUTH_FAILED));
+
+        // the first signature character, the last one carries bits a decoder may ignore.
+        let mut tampered = token(KEY, now() + 60);
+        let at = tampered.rfind('.').unwrap() + 1;
+        let flipped = if tampered[at..].starts_with('A') {
+            "B"
+        } else {
+            "A"
+        };
+        tampered.replace_range(at..at + 1, flipped);
+        assert_eq!(verifier.verify(&tampered).err(), Some(status::AUTH_FAILED));
+    }
+
+    #[test]
+    fn rejects_other_algorithms_and_malformed_tokens() {
+        let verifier = TokenVerifier::new(KEY.to_vec());
+        let none = sign(
+            KEY,
+            r#"{"alg":"none"}"#,
+            r#"{"sub":"batch","exp":4102444800}"#,
+        );
+        assert_eq!(verifier.verify(&none).err(), Some(status::AUTH_FAILED));
+        assert_eq!(verifier.verify("a.b").err(), Some(status::AUTH_FAILED));
+        assert_eq!(verifier.verify("a.b.c.d").err(), Some(status::AUTH_FAILED));
+    }
+}
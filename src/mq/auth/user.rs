@@ -0,0 +1,182 @@
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+const HASH_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+
+pub struct User {
+    pub name: String,
+    iterations: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl User {
+    pub fn new(name: String, password: &[u8]) -> Result<User, Box<dyn Error>> {
+        let mut salt = vec![0u8; SALT_LEN];
+        getrandom::getrandom(&mut salt)?;
+        let hash = Self::derive(password, &salt, DEFAULT_ITERATIONS);
+        Ok(User {
+            name,
+            iterations: DEFAULT_ITERATIONS,
+            salt,
+            hash,
+        })
+    }
+
+    // line format of the users file: name:iterations:salt(hex):hash(hex)
+    pub fn parse(line: &str) -> Option<User> {
+        let mut parts = line.trim().split(':');
+        let name = parts.next()?.to_string();
+        let iterations: u32 = parts.next()?.parse().ok()?;
+        let salt = decode_hex(parts.next()?)?;
+        let hash = decode_hex(parts.next()?)?;
+        if name.is_empty() || iterations == 0 || hash.len() != HASH_LEN || parts.next().is_some() {
+            return None;
+        }
+        Some(User {
+            name,
+            iterations,
+            salt,
+            hash,
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.name,
+            self.iterations,
+            encode_hex(&self.salt),
+            encode_hex(&self.hash)
+        )
+    }
+
+    pub fn verify(&self, password: &[u8]) -> bool {
+        let hash = Self::derive(password, &self.salt, self.iterations);
+        // constant-time comparison, so the mismatch position is not leaked.
+        hash.iter()
+            .zip(self.hash.iter())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    }
+
+    fn derive(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut hash = vec![0u8; HASH_LEN];
+        pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut hash);
+        hash
+    }
+}
+
+#[derive(Default)]
+pub struct UserStore {
+    users: HashMap<String, User>,
+}
+
+impl UserStore {
+    pub fn new() -> UserStore {
+        UserStore::default()
+    }
+
+    pub fn load(path: &str) -> Result<UserStore, Box<dyn Error>> {
+        let mut store = UserStore::new();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match User::parse(line) {
+                Some(user) => store.add(user),
+                None => println!("[mq] malformed entry in users file at line {}", i + 1),
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn add(&mut self, user: User) {
+        self.users.insert(user.name.clone(), user);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub fn authenticate(&self, name: &str, password: &[u8]) -> bool {
+        match self.users.get(name) {
+            Some(user) => user.verify(password),
+            None => false,
+        }
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fewer iterations than DEFAULT_ITERATIONS, debug builds are slow at hashing.
+    fn user(name: &str, password: &[u8]) -> User {
+        let salt = b"0123456789abcdef".to_vec();
+        User {
+            name: name.to_string(),
+            iterations: 1000,
+            hash: User::derive(password, &salt, 1000),
+            salt,
+        }
+    }
+
+    #[test]
+    fn line_round_trip() {
+        let line = user("alice", b"secret").to_line();
+        let parsed = User::parse(&line).unwrap();
+        assert_eq!(parsed.name, "alice");
+        assert_eq!(parsed.to_line(), line);
+    }
+
+    #[test]
+    fn verifies_the_password() {
+        let parsed = User::parse(&user("alice", b"secret").to_line()).unwrap();
+        assert!(parsed.verify(b"secret"));
+        assert!(!parsed.verify(b"Secret"));
+        assert!(!parsed.verify(b""));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let hash = "00".repeat(HASH_LEN);
+        assert!(User::parse(&format!("alice:1000:00ff:{}", hash)).is_some());
+        assert!(User::parse(&format!(":1000:00ff:{}", hash)).is_none());
+        assert!(User::parse(&format!("alice:0:00ff:{}", hash)).is_none());
+        assert!(User::parse(&format!("alice:many:00ff:{}", hash)).is_none());
+        assert!(User::parse(&format!("alice:1000:0ff:{}", hash)).is_none());
+        assert!(User::parse(&format!("alice:1000:00ff:{}", &hash[2..])).is_none());
+        assert!(User::parse(&format!("alice:1000:00ff:{}:extra", hash)).is_none());
+        assert!(User::parse("alice:1000:00ff").is_none());
+    }
+
+    #[test]
+    fn store_authenticates_known_users_only() {
+        let mut store = UserStore::new();
+        store.add(user("alice", b"secret"));
+        assert!(store.authenticate("alice", b"secret"));
+        assert!(!store.authenticate("alice", b"wrong"));
+        assert!(!store.authenticate("bob", b"secret"));
+    }
+}
@@ -1,8 +1,18 @@
+use crate::mq::auth::manager::AuthManager;
+use crate::mq::auth::perm::PermissionStore;
+use crate::mq::auth::user::UserStore;
+use crate::mq::breaker::shutdown::Shutdown;
 use crate::mq::common::context::RuntimeContext;
+use crate::mq::common::sync::{SafeLock, SafeRwLock};
+#[cfg(feature = "tokio")]
+use crate::mq::host::async_manager::AsyncHostManager;
 use crate::mq::host::manager::HostManager;
 use crate::mq::host::vhost::VirtualHost;
-use crate::mq::net::factory::PhysicalConnectionFactory;
+use crate::mq::net::acl::NetworkAcl;
+use crate::mq::net::limits::Limits;
 use crate::mq::net::manager::PhysicalConnectionManager;
+use crate::mq::net::reactor::Reactor;
+use crate::mq::net::registry::ConnectionInfo;
 use crate::mq::protocol::raw::RawData;
 use crate::mq::queue::queue_object::QueueObject;
 use std::net::{TcpListener, ToSocketAddrs};
@@ -11,25 +21,47 @@ use std::thread;
 
 pub struct Breaker {
     tcp_listener: TcpListener,
+    acl: NetworkAcl,
+    limits: Limits,
+    workers: usize,
+    shutdown: Arc<Shutdown>,
     host_manager: Option<Arc<RwLock<HostManager>>>,
     physical_connection_manager: Option<Arc<RwLock<PhysicalConnectionManager>>>,
 }
 
 impl Breaker {
-    pub fn new<A: ToSocketAddrs>(addr: A) -> Breaker {
+    pub fn new<A: ToSocketAddrs>(addr: A, shutdown: Arc<Shutdown>) -> Breaker {
         Breaker {
             tcp_listener: TcpListener::bind(addr).unwrap(),
+            acl: NetworkAcl::new(),
+            limits: Limits::new(),
+            workers: 1,
+            shutdown,
             host_manager: None,
             physical_connection_manager: None,
         }
     }
 
-    pub fn init_managers(&mut self, self_ref: Arc<Mutex<Breaker>>) {
+    pub fn set_acl(&mut self, acl: NetworkAcl) {
+        self.acl = acl;
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers;
+    }
+
+    pub fn init_managers(&mut self, self_ref: Arc<Mutex<Breaker>>, auth_manager: AuthManager) {
         let host_manager = Arc::new(RwLock::new(HostManager::new().init(self_ref.clone())));
         self.host_manager = Some(host_manager.clone());
 
         self.physical_connection_manager = Some(Arc::new(RwLock::new(
-            PhysicalConnectionManager::new().init(self_ref.clone(), host_manager.clone()),
+            PhysicalConnectionManager::new()
+                .init(self_ref.clone(), host_manager.clone())
+                .set_auth_manager(Arc::new(auth_manager)),
         )));
     }
 
@@ -44,96 +76,176 @@ impl Breaker {
         })
     }
 
-    pub fn send_raw_to_host(&mut self, data: RawData, err_handle: &mut u16) -> Option<QueueObject> {
+    // routing only needs the host manager's read lock, like frames coming in on a connection.
+    pub fn send_raw_to_host(&self, data: RawData, err_handle: &mut u16) -> Option<QueueObject> {
         self.host_manager
-            .as_mut()?
-            .write()
-            .unwrap()
+            .as_ref()?
+            .safe_read()
             .send_raw_to_host(data, err_handle)
     }
 
-    pub fn stop_worker(&mut self) {
-        self.physical_connection_manager
-            .as_mut()
-            .unwrap()
-            .write()
-            .unwrap()
-            .close();
+    pub fn listen(&mut self) -> Result<(), ()> {
+        let listener = self.tcp_listener.try_clone().map_err(|_| ())?;
+        let mut reactor = Reactor::new(
+            listener,
+            self.acl.clone(),
+            self.limits,
+            self.physical_connection_manager.clone().ok_or(())?,
+            self.workers,
+            self.shutdown.clone(),
+        )
+        .map_err(|_| ())?;
+        reactor
+            .run()
+            .map_err(|e| println!("[mq] reactor stopped: {}", e))
     }
 
-    pub fn listen(&mut self) -> Result<(), ()> {
-        for incoming in self.tcp_listener.incoming() {
-            if let Ok(stream) = incoming {
-                // Note: this is a hack to prevent self-connection
-                // in the meantime, this hack is just temporary.
-                if stream.peer_addr().unwrap() == stream.local_addr().unwrap() {
-                    continue;
-                }
-                dbg!("new connection");
-                let conn = PhysicalConnectionFactory::new()
-                    .set_manager_proxy(self.physical_connection_manager.clone())
-                    .set_stream(stream)
-                    .fetch();
-                self.physical_connection_manager
-                    .as_mut()
-                    .unwrap()
-                    .write()
-                    .unwrap()
-                    .add(conn?);
-            }
-        }
-        Ok(())
+    // everything the tokio runtime needs, taken out so the breaker is not locked across awaits.
+    #[cfg(feature = "tokio")]
+    fn async_parts(&self) -> Option<AsyncParts> {
+        Some(AsyncParts {
+            listener: self.tcp_listener.try_clone().ok()?,
+            acl: self.acl.clone(),
+            limits: self.limits,
+            manager: self.physical_connection_manager.clone()?,
+            shutdown: self.shutdown.clone(),
+        })
     }
 }
 
+#[cfg(feature = "tokio")]
+struct AsyncParts {
+    listener: TcpListener,
+    acl: NetworkAcl,
+    limits: Limits,
+    manager: Arc<RwLock<PhysicalConnectionManager>>,
+    shutdown: Arc<Shutdown>,
+}
+
 pub struct Core {
     breaker: Arc<Mutex<Breaker>>,
+    // kept outside the breaker, which stays locked for as long as it serves.
+    shutdown: Arc<Shutdown>,
+    connection_manager: Arc<RwLock<PhysicalConnectionManager>>,
 }
 
 impl Core {
     pub fn new(ctx: Arc<Mutex<RuntimeContext>>) -> Core {
-        let addr = format!("{}:{}", ctx.lock().unwrap().local_host, ctx.lock().unwrap().local_port);
-        let breaker = Breaker::new(addr);
+        let (addr, shutdown) = {
+            // both fields are read under one guard, locking twice in format! deadlocks.
+            let ctx = ctx.safe_lock();
+            (
+                format!("{}:{}", ctx.local_host, ctx.local_port),
+                Shutdown::new(ctx.shutdown_timeout),
+            )
+        };
+        let mut breaker = Breaker::new(addr, shutdown.clone());
+        breaker.set_acl(ctx.safe_lock().acl.clone());
+        breaker.set_limits(ctx.safe_lock().limits);
+        breaker.set_workers(ctx.safe_lock().workers);
+
+        let mut auth_manager = AuthManager::new();
+        if let Some(path) = &ctx.safe_lock().users_file {
+            match UserStore::load(path) {
+                Ok(users) => auth_manager = auth_manager.set_users(users),
+                Err(e) => panic!("[mq] failed to load users file {}: {}", path, e),
+            }
+        } else {
+            println!("[mq] no users file configured, password logins are disabled");
+        }
+        if let Some(key) = &ctx.safe_lock().token_key {
+            auth_manager = auth_manager.set_token_key(key.as_bytes().to_vec());
+        }
+        if let Some(path) = &ctx.safe_lock().permissions_file {
+            match PermissionStore::load(path) {
+                Ok(permissions) => auth_manager = auth_manager.set_permissions(permissions),
+                Err(e) => panic!("[mq] failed to load permissions file {}: {}", path, e),
+            }
+        }
 
         let self_ref = Arc::new(Mutex::new(breaker));
         self_ref
             .lock()
             .as_mut()
             .unwrap()
-            .init_managers(self_ref.clone());
+            .init_managers(self_ref.clone(), auth_manager);
 
         // default host
         let default_name = String::from("MQ_HOST");
         self_ref
-            .lock()
-            .unwrap()
+            .safe_lock()
             .host_manager
             .clone()
             .unwrap()
-            .write()
-            .unwrap()
+            .safe_write()
             .add(default_name.clone(), VirtualHost::new(default_name));
 
-        for h in &ctx.lock().unwrap().hosts {
+        let ctx = ctx.safe_lock();
+        for h in &ctx.hosts {
+            let mut vhost = VirtualHost::new(h.clone());
+            if let Some(acl) = ctx.host_acls.get(h) {
+                vhost = vhost.set_acl(acl.clone());
+            }
             self_ref
-                .lock()
-                .unwrap()
+                .safe_lock()
                 .host_manager
                 .clone()
                 .unwrap()
-                .write()
-                .unwrap()
-                .add(h.clone(), VirtualHost::new(h.clone()));
+                .safe_write()
+                .add(h.clone(), vhost);
         }
 
-        Core { breaker: self_ref }
+        let connection_manager = self_ref
+            .safe_lock()
+            .physical_connection_manager
+            .clone()
+            .unwrap();
+        Core {
+            breaker: self_ref,
+            shutdown,
+            connection_manager,
+        }
     }
 
     pub fn start(&mut self) {
-        self.breaker.lock().unwrap().start_worker();
+        self.breaker.safe_lock().start_worker();
+    }
+
+    // start() returns once the connections are drained or the shutdown timeout has passed.
+    pub fn stop(&self) {
+        self.shutdown.request();
+    }
+
+    pub fn shutdown_handle(&self) -> Arc<Shutdown> {
+        self.shutdown.clone()
+    }
+
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connection_manager.safe_read().list()
+    }
+
+    pub fn close_connection(&self, id: u64) -> bool {
+        self.connection_manager.safe_read().force_close(id)
+    }
+
+    // serves connections on the current tokio runtime instead of the reactor.
+    #[cfg(feature = "tokio")]
+    pub async fn serve(&self) -> std::io::Result<()> {
+        let parts = self.breaker.safe_lock().async_parts();
+        let parts = parts.ok_or_else(|| std::io::Error::other("breaker is not initialized"))?;
+        crate::mq::net::async_rt::serve(
+            parts.listener,
+            parts.acl,
+            parts.limits,
+            parts.manager,
+            parts.shutdown,
+        )
+        .await
     }
 
-    pub fn stop(&mut self) {
-        self.breaker.lock().unwrap().stop_worker();
+    #[cfg(feature = "tokio")]
+    pub fn async_host_manager(&self) -> AsyncHostManager {
+        let host_manager = self.connection_manager.safe_read().host_manager.clone();
+        AsyncHostManager::new(host_manager.unwrap())
     }
 }
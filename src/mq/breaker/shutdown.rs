@@ -0,0 +1,40 @@
+use crate::mq::common::sync::SafeLock;
+use mio::Waker;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// shared between the signal handler, the api and the runtime serving connections.
+pub struct Shutdown {
+    requested: AtomicBool,
+    // how long in-flight frames and unsent replies may take once shutdown is requested.
+    pub timeout: Duration,
+    wakers: Mutex<Vec<Arc<Waker>>>,
+}
+
+impl Shutdown {
+    pub fn new(timeout: Duration) -> Arc<Shutdown> {
+        Arc::new(Shutdown {
+            requested: AtomicBool::new(false),
+            timeout,
+            wakers: Mutex::new(Vec::new()),
+        })
+    }
+
+    // a reactor blocked in poll() is woken up right away instead of on its next tick.
+    pub fn register(&self, waker: Arc<Waker>) {
+        self.wakers.safe_lock().push(waker);
+    }
+
+    pub fn request(&self) {
+        if !self.requested.swap(true, Ordering::SeqCst) {
+            for waker in self.wakers.safe_lock().iter() {
+                waker.wake().unwrap_or(());
+            }
+        }
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
@@ -1,7 +1,24 @@
+use crate::mq::net::acl::NetworkAcl;
+use crate::mq::net::limits::Limits;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
 pub struct RuntimeContext {
     pub local_host: String,
     pub local_port: u16,
     pub hosts: Vec<String>,
+    pub workers: usize,
+    // serve connections on tokio instead of the mio reactor, needs the "tokio" feature.
+    pub async_runtime: bool,
+    // how long connections get to drain on shutdown.
+    pub shutdown_timeout: Duration,
+    pub acl: NetworkAcl,
+    pub host_acls: HashMap<String, NetworkAcl>,
+    pub limits: Limits,
+    pub users_file: Option<String>,
+    pub permissions_file: Option<String>,
+    pub token_key: Option<String>,
 }
 
 impl RuntimeContext {
@@ -10,6 +27,15 @@ impl RuntimeContext {
             local_host,
             local_port,
             hosts: Vec::new(),
+            workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            async_runtime: false,
+            shutdown_timeout: Duration::from_secs(5),
+            acl: NetworkAcl::new(),
+            host_acls: HashMap::new(),
+            limits: Limits::new(),
+            users_file: None,
+            permissions_file: None,
+            token_key: None,
         }
     }
 }
@@ -0,0 +1,41 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// a thread that panics while holding a lock poisons it, and every unwrap() after that panics too,
+// taking all the other connections down with it. what the locks guard stays usable,
+// a queue keeps its messages, so the poison is reported once, cleared, and the guard handed out.
+pub trait SafeLock<T> {
+    fn safe_lock(&self) -> MutexGuard<'_, T>;
+}
+
+pub trait SafeRwLock<T> {
+    fn safe_read(&self) -> RwLockReadGuard<'_, T>;
+    fn safe_write(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> SafeLock<T> for Mutex<T> {
+    fn safe_lock(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|poisoned| {
+            println!("[mq] recovered a poisoned lock");
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+impl<T> SafeRwLock<T> for RwLock<T> {
+    fn safe_read(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(|poisoned| {
+            println!("[mq] recovered a poisoned lock");
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+
+    fn safe_write(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(|poisoned| {
+            println!("[mq] recovered a poisoned lock");
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
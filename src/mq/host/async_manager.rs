@@ -0,0 +1,87 @@
+use crate::mq::common::sync::SafeRwLock;
+use crate::mq::host::manager::HostManager;
+use crate::mq::protocol::raw::{IOType, Raw, RawCommand, RawData, RawMessage};
+use crate::mq::queue::queue_object::QueueObject;
+use crate::mq::routing::key::RoutingKey;
+use std::sync::{Arc, RwLock};
+use tokio::task;
+
+// async facade over the HostManager for services that embed the broker in a tokio runtime.
+// the work itself takes std locks, so it runs on the blocking pool.
+#[derive(Clone)]
+pub struct AsyncHostManager {
+    host_manager: Arc<RwLock<HostManager>>,
+}
+
+impl AsyncHostManager {
+    pub fn new(host_manager: Arc<RwLock<HostManager>>) -> AsyncHostManager {
+        AsyncHostManager { host_manager }
+    }
+
+    // returns the feedback of the host together with its status code.
+    pub async fn send_raw_to_host(&self, raw: RawData) -> (Option<QueueObject>, u16) {
+        let host_manager = self.host_manager.clone();
+        task::spawn_blocking(move || {
+            let mut err_handle: u16 = 0;
+            let result = host_manager
+                .safe_read()
+                .send_raw_to_host(raw, &mut err_handle);
+            (result, err_handle)
+        })
+        .await
+        .unwrap_or((None, 0))
+    }
+
+    pub async fn push(&self, vhost: &str, routing_key: RoutingKey, data: Vec<u8>) -> u16 {
+        let raw = Self::raw(vhost, routing_key, Raw::Message(RawMessage::Push(data)));
+        self.send_raw_to_host(raw).await.1
+    }
+
+    pub async fn fetch(&self, vhost: &str, routing_key: RoutingKey) -> (Option<QueueObject>, u16) {
+        let mut raw = Self::raw(
+            vhost,
+            routing_key,
+            Raw::Message(RawMessage::Fetch(Vec::new())),
+        );
+        raw.io_type = IOType::Read;
+        self.send_raw_to_host(raw).await
+    }
+
+    pub async fn declare_queue(&self, vhost: &str, routing_key: RoutingKey, name: &str) -> u16 {
+        let cmd = RawCommand::NewQueue(name.as_bytes().to_vec());
+        self.command(vhost, routing_key, cmd).await
+    }
+
+    pub async fn declare_exchange(&self, vhost: &str, routing_key: RoutingKey, name: &str) -> u16 {
+        let cmd = RawCommand::NewExchange(name.as_bytes().to_vec());
+        self.command(vhost, routing_key, cmd).await
+    }
+
+    pub async fn drop_queue(&self, vhost: &str, routing_key: RoutingKey, name: &str) -> u16 {
+        let cmd = RawCommand::DropQueue(name.as_bytes().to_vec());
+        self.command(vhost, routing_key, cmd).await
+    }
+
+    pub async fn drop_exchange(&self, vhost: &str, routing_key: RoutingKey, name: &str) -> u16 {
+        let cmd = RawCommand::DropExchange(name.as_bytes().to_vec());
+        self.command(vhost, routing_key, cmd).await
+    }
+
+    async fn command(&self, vhost: &str, routing_key: RoutingKey, cmd: RawCommand) -> u16 {
+        let raw = Self::raw(vhost, routing_key, Raw::Command(cmd));
+        self.send_raw_to_host(raw).await.1
+    }
+
+    fn raw(vhost: &str, routing_key: RoutingKey, raw: Raw) -> RawData {
+        RawData {
+            raw,
+            channel: String::new(),
+            virtual_host: vhost.to_string(),
+            routing_key,
+            io_type: IOType::Write,
+            content_type: 0,
+            peer_addr: None,
+            connection: None,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::mq::breaker::core::Breaker;
+use crate::mq::common::sync::SafeRwLock;
 use crate::mq::host::vhost::VirtualHost;
 use crate::mq::protocol::raw::RawData;
+use crate::mq::protocol::status;
 use crate::mq::queue::queue_object::QueueObject;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
@@ -47,18 +49,24 @@ impl HostManager {
         let io_type = &raw.io_type;
 
         if let Some(vhost) = vhost {
-            vhost.read().unwrap().process_incoming(raw, err_handle)
+            let vhost = vhost.safe_read();
+            if let Some(peer) = raw.peer_addr {
+                if !vhost.acl.permits(&peer.ip()) {
+                    println!("[mq] {} refused by network acl of host {}", peer, host_name);
+                    *err_handle = status::ACCESS_REFUSED;
+                    return Some(QueueObject::new(&host_name, Vec::new()));
+                }
+            }
+            vhost.process_incoming(raw, err_handle)
 
             // todo: I see no difference whether to use read() or write().
             /*match io_type {
                 IOType::Read => {
-                    vhost.read()
-                        .unwrap()
+                    vhost.safe_read()
                         .process_incoming(raw)
                 }
                 IOType::Write => {
-                    vhost.write()
-                        .unwrap()
+                    vhost.safe_write()
                         .process_incoming(raw)
                 }
             }*/
@@ -1,2 +1,4 @@
+#[cfg(feature = "tokio")]
+pub mod async_manager;
 pub mod manager;
 pub mod vhost;
@@ -1,178 +1,464 @@
+use crate::mq::common::sync::SafeRwLock;
+use crate::mq::net::acl::NetworkAcl;
 use crate::mq::protocol::raw::{Raw, RawCommand, RawData, RawMessage};
+use crate::mq::protocol::status;
+use crate::mq::queue::consumer::Consumer;
 use crate::mq::queue::qbase::Queue;
 use crate::mq::queue::queue_object::QueueObject;
-use crate::mq::routing::exchange::Exchange;
+use crate::mq::routing::binding::{Binding, BindingKind, BindingSpec, BindingTarget};
+use crate::mq::routing::cache::RouteCache;
+use crate::mq::routing::exchange::{join, Exchange};
 use crate::mq::routing::key::RoutingKey;
 use std::sync::{Arc, RwLock};
 
 pub struct VirtualHost {
     pub name: String,
     base_exchange: Arc<RwLock<Exchange>>,
+    routes: Arc<RouteCache>,
+    pub acl: NetworkAcl,
 }
 
 impl VirtualHost {
     pub fn new(name: String) -> VirtualHost {
-        let exchange = Arc::from(RwLock::from(Exchange::new(String::from("mq-root"))));
-        exchange.write().unwrap().init(exchange.clone());
+        let routes = Arc::new(RouteCache::new());
+        let exchange = Arc::from(RwLock::from(Exchange::new(
+            String::from("mq-root"),
+            routes.clone(),
+        )));
+        exchange.safe_write().init(exchange.clone());
         VirtualHost {
             name,
             base_exchange: exchange,
+            routes,
+            acl: NetworkAcl::new(),
         }
     }
 
+    pub fn set_acl(mut self, acl: NetworkAcl) -> Self {
+        self.acl = acl;
+        self
+    }
+
     pub fn add_exchange(&mut self, name: String, routing_key: RoutingKey) -> &mut Self {
-        let base = self.base_exchange.write().unwrap().walk(routing_key, 0);
+        let base = self.base_exchange.safe_read().walk(&routing_key);
         if let Some(exc) = base {
-            exc[0].write().unwrap().add_exchange(name);
+            exc.safe_write().add_exchange(name);
         }
         self
     }
 
     pub fn add_queue(&mut self, name: String, routing_key: RoutingKey) -> &mut Self {
-        let base = self.base_exchange.write().unwrap().walk(routing_key, 0);
+        let base = self.base_exchange.safe_read().walk(&routing_key);
         if let Some(exc) = base {
-            exc[0].write().unwrap().add_queue(&name);
+            exc.safe_write().add_queue(&name);
         }
         self
     }
 
     pub fn get_queue(&self, name: &String, routing_key: RoutingKey) -> Option<Arc<RwLock<Queue>>> {
-        let base = self
-            .base_exchange
-            .read()
-            .unwrap()
-            .walk_readonly(routing_key, 0);
+        let base = self.base_exchange.safe_read().walk(&routing_key);
         if let Some(exc) = base {
-            exc.get(0)?.read().unwrap().get_queue(name)
+            exc.safe_read().get_queue(name)
         } else {
             None
         }
     }
 
     pub fn drop_exchange(&mut self, name: String, routing_key: RoutingKey) -> &mut Self {
-        let base = self.base_exchange.write().unwrap().walk(routing_key, 0);
+        let base = self.base_exchange.safe_read().walk(&routing_key);
         if let Some(exc) = base {
-            exc[0].write().unwrap().remove_exchange(name);
+            self.remove_exchange(&exc, name);
         }
         self
     }
 
     pub fn drop_queue(&mut self, name: String, routing_key: RoutingKey) -> &mut Self {
-        let base = self.base_exchange.write().unwrap().walk(routing_key, 0);
+        let base = self.base_exchange.safe_read().walk(&routing_key);
         if let Some(exc) = base {
-            exc[0].write().unwrap().remove_queue(name);
+            self.remove_queue(&exc, name);
         }
         self
     }
 
+    // a removed exchange or queue takes the bindings to it along, wherever they were made.
+    fn remove_exchange(&self, exc: &Arc<RwLock<Exchange>>, name: String) {
+        let path = join(exc.safe_read().path(), &name);
+        if exc.safe_write().remove_exchange(name).is_some() {
+            self.base_exchange
+                .safe_read()
+                .unbind(BindingKind::Exchange, &path);
+        }
+    }
+
+    fn remove_queue(&self, exc: &Arc<RwLock<Exchange>>, name: String) {
+        let path = join(exc.safe_read().path(), &name);
+        if exc.safe_write().remove_queue(name).is_some() {
+            self.base_exchange
+                .safe_read()
+                .unbind(BindingKind::Queue, &path);
+        }
+    }
+
+    // the target is looked up before the exchange bound to is locked, it may lie below it.
+    fn bind(&self, exc: &Arc<RwLock<Exchange>>, spec: BindingSpec) -> u16 {
+        let segments = spec.segments();
+        let target = match spec.kind {
+            BindingKind::Queue => segments.split_last().and_then(|(name, parent)| {
+                let parent = self.base_exchange.safe_read().find(parent)?;
+                let queue = parent.safe_read().get_queue(name)?;
+                Some(BindingTarget::Queue(queue))
+            }),
+            BindingKind::Exchange => self
+                .base_exchange
+                .safe_read()
+                .find(&segments)
+                .map(BindingTarget::Exchange),
+        };
+        match target {
+            Some(target) => {
+                exc.safe_read().add_binding(Binding::new(spec, target));
+                status::OK
+            }
+            None => {
+                println!("[mq] binding target not found: {}", spec.describe());
+                status::NOT_FOUND
+            }
+        }
+    }
+
+    // the queues a message goes to. a direct route names one queue, a topic route may use
+    // '*' and '#' in its exchange slots, and '*' or '#' in the queue slot for every queue.
+    // a fanout route goes to every queue of its exchange, with '#' in the queue slot
+    // to every queue below it as well. the queues and exchanges bound with a matching key
+    // get the message too, and they are all a headers route goes to.
+    // bindings are only followed for pushes, reads never reach a queue through one.
+    fn resolve(
+        &self,
+        routing: &RoutingKey,
+        queue_name: &String,
+        bindings: bool,
+    ) -> Option<Vec<Arc<RwLock<Queue>>>> {
+        let mut exchanges = match routing {
+            RoutingKey::Topic(_) => {
+                let mut matched = vec![];
+                self.base_exchange
+                    .safe_read()
+                    .walk_topic(&routing.exchange_path(), &mut matched);
+                matched
+            }
+            _ => {
+                vec![self.base_exchange.safe_read().walk(routing)?]
+            }
+        };
+
+        // bound exchanges are routed through like the matched ones, each of them once.
+        // no exchange lock is held while the next one is taken.
+        let mut queues: Vec<Arc<RwLock<Queue>>> = vec![];
+        let mut next = 0;
+        while next < exchanges.len() {
+            let exc = exchanges[next].clone();
+            exc.safe_read()
+                .route(routing, queue_name, bindings, &mut queues, &mut exchanges);
+            next += 1;
+        }
+        if queues.is_empty() {
+            None
+        } else {
+            Some(queues)
+        }
+    }
+
     pub fn process_incoming(&self, raw: RawData, err_handle: &mut u16) -> Option<QueueObject> {
-        // todo: implement advanced routing: * # ...
         // always remember that the last value of RoutingKey is the name of the Queue.
 
         // println!("[mq] incoming data: {:?}", raw);
         println!("[mq] incoming data.");
         let routing = raw.routing_key;
-        let routing_copied = routing.clone();
         let host = raw.virtual_host.trim_end_matches("\0").to_string();
 
-        let queue_name = match routing {
-            RoutingKey::Direct(key) => key[3].clone(),
-            RoutingKey::Topic(key) => key[3].clone(),
-            RoutingKey::Fanout(key) => key[3].clone(),
-        };
+        let queue_name = routing.queue().clone();
 
-        let exchange = self.base_exchange.write().unwrap().walk(routing_copied, 0);
-        if let Some(exc) = exchange {
-            match raw.raw {
-                Raw::Command(cmd) => {
-                    match cmd {
-                        RawCommand::NewQueue(data) => {
-                            // dbg!("new queue");
-                            let queue_name = String::from_utf8(data)
-                                .unwrap()
-                                .trim_end_matches("\0")
-                                .trim()
-                                .to_string();
-                            exc[0].write().unwrap().add_queue(&queue_name);
-                        }
-                        RawCommand::NewExchange(data) => {
-                            // dbg!("new exchange");
-                            let exchange_name = String::from_utf8(data)
-                                .unwrap()
-                                .trim_end_matches("\0")
-                                .trim()
-                                .to_string();
-                            exc[0].write().unwrap().add_exchange(exchange_name);
-                        }
-                        RawCommand::NewBinding(_) => {
-                            // dbg!("new binding");
-                        }
-                        RawCommand::DropQueue(data) => {
-                            let queue_name = String::from_utf8(data)
-                                .unwrap()
-                                .trim_end_matches("\0")
-                                .trim()
-                                .to_string();
-                            exc[0].write().unwrap().remove_queue(queue_name);
-                        }
-                        RawCommand::DropExchange(data) => {
-                            // dbg!("drop exchange");
-                            let exchange_name = String::from_utf8(data)
-                                .unwrap()
-                                .trim_end_matches("\0")
-                                .trim()
-                                .to_string();
-                            exc[0].write().unwrap().remove_exchange(exchange_name);
-                        }
-                        RawCommand::DropBinding(_) => {
-                            // dbg!("drop binding");
-                        }
-                        RawCommand::Nop => {
-                            // dbg!("nop");
-                        }
+        match raw.raw {
+            Raw::Command(cmd) => {
+                // commands walk the tree, and take the write lock of the one exchange they change.
+                let exc = self.base_exchange.safe_read().walk(&routing)?;
+                match cmd {
+                    RawCommand::NewQueue(data) => {
+                        // dbg!("new queue");
+                        let queue_name = String::from_utf8(data)
+                            .unwrap()
+                            .trim_end_matches("\0")
+                            .trim()
+                            .to_string();
+                        exc.safe_write().add_queue(&queue_name);
+                    }
+                    RawCommand::NewExchange(data) => {
+                        // dbg!("new exchange");
+                        let exchange_name = String::from_utf8(data)
+                            .unwrap()
+                            .trim_end_matches("\0")
+                            .trim()
+                            .to_string();
+                        exc.safe_write().add_exchange(exchange_name);
+                    }
+                    RawCommand::NewBinding(data) => {
+                        // dbg!("new binding");
+                        // bindings are answered, so a client learns when one was not made.
+                        *err_handle = match BindingSpec::parse(&data) {
+                            Some(spec) => self.bind(&exc, spec),
+                            None => {
+                                println!("[mq] malformed binding");
+                                status::BAD_REQUEST
+                            }
+                        };
+                        return Some(QueueObject::new(&host, Vec::new()));
+                    }
+                    RawCommand::DropQueue(data) => {
+                        let queue_name = String::from_utf8(data)
+                            .unwrap()
+                            .trim_end_matches("\0")
+                            .trim()
+                            .to_string();
+                        self.remove_queue(&exc, queue_name);
+                    }
+                    RawCommand::DropExchange(data) => {
+                        // dbg!("drop exchange");
+                        let exchange_name = String::from_utf8(data)
+                            .unwrap()
+                            .trim_end_matches("\0")
+                            .trim()
+                            .to_string();
+                        self.remove_exchange(&exc, exchange_name);
+                    }
+                    RawCommand::DropBinding(data) => {
+                        // dbg!("drop binding");
+                        *err_handle = match BindingSpec::parse(&data) {
+                            Some(spec) if exc.safe_read().remove_binding(&spec) => status::OK,
+                            Some(spec) => {
+                                println!("[mq] binding not found: {}", spec.describe());
+                                status::NOT_FOUND
+                            }
+                            None => {
+                                println!("[mq] malformed binding");
+                                status::BAD_REQUEST
+                            }
+                        };
+                        return Some(QueueObject::new(&host, Vec::new()));
+                    }
+                    RawCommand::ListBindings => {
+                        // one binding per line, as NewBinding takes them.
+                        let listing: String = exc
+                            .safe_read()
+                            .get_bindings()
+                            .iter()
+                            .map(|spec| spec.describe() + "\n")
+                            .collect();
+                        return Some(QueueObject::new(&host, listing.into_bytes()));
+                    }
+                    RawCommand::Nop => {
+                        // dbg!("nop");
                     }
                 }
-                Raw::Message(data) => {
-                    // dbg!("message");
-                    match data {
-                        RawMessage::Push(data) => {
-                            // dbg!("push");
-                            exc[0]
-                                .read()
-                                .unwrap()
-                                .get_queue(&queue_name)?
-                                .write()
-                                .unwrap()
-                                .push_back(QueueObject::new(&self.name, data))
+            }
+            Raw::Message(data) => {
+                // messages only take the locks of their queues, the route is usually cached.
+                // headers differ from message to message, their routes are not worth keeping.
+                let bindings = matches!(data, RawMessage::Push(_));
+                let queues = match routing {
+                    RoutingKey::Headers(..) => {
+                        Arc::new(self.resolve(&routing, &queue_name, bindings)?)
+                    }
+                    _ => self.routes.resolve(&routing, bindings, || {
+                        self.resolve(&routing, &queue_name, bindings)
+                    })?,
+                };
+                // dbg!("message");
+                match data {
+                    RawMessage::Push(data) => {
+                        // dbg!("push");
+                        let obj =
+                            QueueObject::new(&self.name, data).set_content_type(raw.content_type);
+                        for queue in queues.iter() {
+                            queue.safe_write().push_back(obj.clone());
                         }
-                        RawMessage::Fetch(data) => {
-                            // dbg!("fetch");
-                            match exc[0]
-                                .read()
-                                .unwrap()
-                                .get_queue(&queue_name)?
-                                .write()
-                                .unwrap()
-                                .pop_front() {
-                                Some(obj) => return Some(obj),
-                                None => {
-                                    *err_handle = 0xf;
-                                    return Some(QueueObject::new(&host, Vec::new()));
-                                }
-                            };
-                            // the 'write' for queue is temporary. but I have no idea how to optimize it.
+                    }
+                    RawMessage::Fetch(_) => {
+                        // dbg!("fetch");
+                        // a topic fetch takes from the first matching queue that has a message.
+                        for queue in queues.iter() {
+                            if let Some(obj) = queue.safe_write().pop_front() {
+                                return Some(obj);
+                            }
                         }
-                        RawMessage::Nop => {
-                            // dbg!("nop");
+                        *err_handle = 0xf;
+                        return Some(QueueObject::new(&host, Vec::new()));
+                        // the 'write' for queue is temporary. but I have no idea how to optimize it.
+                    }
+                    RawMessage::Subscribe(_) => {
+                        if let Some(handle) = raw.connection {
+                            println!(
+                                "[mq] connection {} subscribed to {} queue(s) as {} on channel {}",
+                                handle.id,
+                                queues.len(),
+                                queue_name,
+                                raw.channel
+                            );
+                            for queue in queues.iter() {
+                                queue
+                                    .safe_write()
+                                    .subscribe(Consumer::new(handle.clone(), raw.channel.clone()));
+                            }
                         }
                     }
-                }
-                Raw::Nop => {
-                    // dbg!("nop");
+                    RawMessage::Cancel(_) => {
+                        if let Some(handle) = raw.connection {
+                            for queue in queues.iter() {
+                                queue.safe_write().cancel(handle.id, &raw.channel);
+                            }
+                        }
+                    }
+                    RawMessage::Nop => {
+                        // dbg!("nop");
+                    }
                 }
             }
+            Raw::Nop => {
+                // dbg!("nop");
+            }
         }
         None
         // Some(QueueObject::new(&host, String::from("success!").into_bytes()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mq::protocol::raw::IOType;
+    use crate::mq::routing::key::RoutingPath;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    fn path(exchanges: &[&str], queue: &str) -> RoutingPath {
+        RoutingPath::new(
+            exchanges.iter().map(|x| x.to_string()).collect(),
+            queue.to_string(),
+        )
+    }
+
+    fn raw(raw: Raw, routing_key: RoutingKey) -> RawData {
+        RawData {
+            raw,
+            channel: String::new(),
+            virtual_host: "vh".to_string(),
+            routing_key,
+            io_type: IOType::Write,
+            content_type: 0,
+            peer_addr: None,
+            connection: None,
+        }
+    }
+
+    fn push(vhost: &VirtualHost, routing_key: RoutingKey, data: &[u8]) {
+        let mut status = status::OK;
+        let push = Raw::Message(RawMessage::Push(data.to_vec()));
+        vhost.process_incoming(raw(push, routing_key), &mut status);
+    }
+
+    fn fetch(vhost: &VirtualHost, routing_key: RoutingKey) -> Option<QueueObject> {
+        let mut status = status::OK;
+        let fetch = Raw::Message(RawMessage::Fetch(vec![]));
+        let obj = vhost.process_incoming(raw(fetch, routing_key), &mut status);
+        if status == status::OK {
+            obj
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn fanout_shares_one_payload_between_queues() {
+        let mut vhost = VirtualHost::new(String::from("vh"));
+        vhost.add_exchange(String::from("orders"), RoutingKey::Direct(path(&[], "")));
+        for name in ["a", "b"] {
+            let parent = RoutingKey::Direct(path(&["orders"], ""));
+            vhost.add_queue(String::from(name), parent);
+        }
+
+        push(&vhost, RoutingKey::Fanout(path(&["orders"], "")), b"m");
+        let a = fetch(&vhost, RoutingKey::Direct(path(&["orders"], "a"))).unwrap();
+        let b = fetch(&vhost, RoutingKey::Direct(path(&["orders"], "b"))).unwrap();
+        assert_eq!(*a.content, b"m");
+        assert!(Arc::ptr_eq(&a.content, &b.content));
+    }
+
+    #[test]
+    fn concurrent_pushes_and_fetches_lose_nothing() {
+        const THREADS: usize = 4;
+        const MESSAGES: usize = 200;
+
+        // a/q and b/q, and c/bound that a passes pushes for "key" on to.
+        let mut vhost = VirtualHost::new(String::from("vh"));
+        for name in ["a", "b", "c"] {
+            vhost.add_exchange(String::from(name), RoutingKey::Direct(path(&[], "")));
+        }
+        for (parent, name) in [("a", "q"), ("b", "q"), ("c", "bound")] {
+            let parent = RoutingKey::Direct(path(&[parent], ""));
+            vhost.add_queue(String::from(name), parent);
+        }
+        let mut status = status::OK;
+        let bind = Raw::Command(RawCommand::NewBinding(b"queue c/bound key".to_vec()));
+        vhost.process_incoming(raw(bind, RoutingKey::Direct(path(&["a"], ""))), &mut status);
+        assert_eq!(status, status::OK);
+
+        // direct pushes reach a/q, topic pushes a/q and b/q, pushes through the binding c/bound.
+        let pushes = [
+            RoutingKey::Direct(path(&["a"], "q")),
+            RoutingKey::Topic(path(&["*"], "q")),
+            RoutingKey::Direct(path(&["a"], "key")),
+        ];
+        let queues = [
+            (
+                RoutingKey::Direct(path(&["a"], "q")),
+                2 * THREADS * MESSAGES,
+            ),
+            (RoutingKey::Direct(path(&["b"], "q")), THREADS * MESSAGES),
+            (
+                RoutingKey::Direct(path(&["c"], "bound")),
+                THREADS * MESSAGES,
+            ),
+        ];
+        let pushers = pushes.len() * THREADS;
+        let finished = AtomicUsize::new(0);
+        let (vhost, finished) = (&vhost, &finished);
+        thread::scope(|scope| {
+            for (route, routing_key) in pushes.iter().enumerate() {
+                for worker in 0..THREADS {
+                    scope.spawn(move || {
+                        for message in 0..MESSAGES {
+                            let data = format!("{}-{}-{}", route, worker, message);
+                            push(vhost, routing_key.clone(), data.as_bytes());
+                        }
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            }
+            for (routing_key, expected) in queues.iter() {
+                scope.spawn(move || {
+                    let mut fetched = HashSet::new();
+                    loop {
+                        // every push is in once all pushers are done, an empty queue is final.
+                        let done = finished.load(Ordering::SeqCst) == pushers;
+                        match fetch(vhost, routing_key.clone()) {
+                            Some(obj) => assert!(fetched.insert(obj.content.to_vec())),
+                            None if done => break,
+                            None => thread::yield_now(),
+                        }
+                    }
+                    assert_eq!(fetched.len(), *expected);
+                });
+            }
+        });
+    }
+}
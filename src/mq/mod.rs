@@ -1,3 +1,4 @@
+pub mod auth;
 pub mod breaker;
 pub mod common;
 pub mod host;
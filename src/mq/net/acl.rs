@@ -0,0 +1,143 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // peers on a dual-stack socket show up as ::ffff:a.b.c.d
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    // "10.0.0.0/8", "fd00::/8", or a single address.
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in cidr: {}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or(format!("invalid prefix length in cidr: {}", s))?,
+            None => max,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+// an address is permitted when no deny entry matches it and,
+// if there are allow entries at all, one of them does.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkAcl {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl NetworkAcl {
+    pub fn new() -> NetworkAcl {
+        NetworkAcl::default()
+    }
+
+    // comma separated lists, as written in config.ini.
+    pub fn parse(allow: Option<&str>, deny: Option<&str>) -> Result<NetworkAcl, String> {
+        let list = |s: Option<&str>| -> Result<Vec<Cidr>, String> {
+            s.unwrap_or("")
+                .split(',')
+                .filter(|x| !x.trim().is_empty())
+                .map(Cidr::from_str)
+                .collect()
+        };
+        Ok(NetworkAcl {
+            allow: list(allow)?,
+            deny: list(deny)?,
+        })
+    }
+
+    pub fn permits(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_matches_its_prefix() {
+        let cidr: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(cidr.contains(&ip("10.1.2.3")));
+        assert!(!cidr.contains(&ip("10.2.0.1")));
+        assert!(cidr.contains(&ip("::ffff:10.1.0.1")));
+
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&ip("192.168.1.1")));
+        assert!(!all.contains(&ip("fd00::1")));
+
+        let single: Cidr = "fd00::1".parse().unwrap();
+        assert!(single.contains(&ip("fd00::1")));
+        assert!(!single.contains(&ip("fd00::2")));
+    }
+
+    #[test]
+    fn rejects_malformed_cidrs() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fd00::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!(NetworkAcl::parse(Some("10.0.0.0/8,nope"), None).is_err());
+    }
+
+    #[test]
+    fn empty_acl_permits_everyone() {
+        let acl = NetworkAcl::parse(None, Some(" ")).unwrap();
+        assert!(acl.permits(&ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let acl =
+            NetworkAcl::parse(Some("10.0.0.0/8, 192.168.0.0/16"), Some("10.9.0.0/16")).unwrap();
+        assert!(acl.permits(&ip("10.1.0.1")));
+        assert!(acl.permits(&ip("192.168.3.4")));
+        assert!(!acl.permits(&ip("10.9.0.1")));
+        assert!(!acl.permits(&ip("172.16.0.1")));
+    }
+
+    #[test]
+    fn deny_only_permits_the_rest() {
+        let acl = NetworkAcl::parse(None, Some("203.0.113.0/24")).unwrap();
+        assert!(!acl.permits(&ip("203.0.113.7")));
+        assert!(acl.permits(&ip("198.51.100.7")));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::mq::breaker::shutdown::Shutdown;
+use crate::mq::common::sync::{SafeLock, SafeRwLock};
+use crate::mq::net::acl::NetworkAcl;
+use crate::mq::net::conn::PhysicalConnection;
+use crate::mq::net::factory::PhysicalConnectionFactory;
+use crate::mq::net::frame::FrameAssembler;
+use crate::mq::net::limits::Limits;
+use crate::mq::net::manager::PhysicalConnectionManager;
+use crate::mq::net::outbox::{Outbox, Outgoing};
+use crate::mq::net::reactor::{READ_CHUNK, TICK};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio::task::JoinSet;
+
+// the tokio counterpart of the reactor: one task per connection instead of a poll loop.
+// frames are still handled by PhysicalConnection::handle, on the blocking pool.
+pub async fn serve(
+    listener: std::net::TcpListener,
+    acl: NetworkAcl,
+    limits: Limits,
+    manager_proxy: Arc<RwLock<PhysicalConnectionManager>>,
+    shutdown: Arc<Shutdown>,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let mut connections = JoinSet::new();
+    let mut tick = tokio::time::interval(TICK);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = tick.tick() => {
+                if shutdown.requested() {
+                    break;
+                }
+                // reap the tasks of connections that are gone.
+                while connections.try_join_next().is_some() {}
+                continue;
+            }
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                println!("[mq] failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        // Note: this is a hack to prevent self-connection
+        // in the meantime, this hack is just temporary.
+        if stream.local_addr().is_ok_and(|local| local == peer) {
+            continue;
+        }
+        if !acl.permits(&peer.ip()) {
+            println!("[mq] connection refused by network acl: {}", peer);
+            continue;
+        }
+
+        let manager_proxy = manager_proxy.clone();
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            if let Err(e) = connection(stream, peer, limits, manager_proxy, shutdown).await {
+                println!("[mq] connection {} failed: {}", peer, e);
+            }
+        });
+    }
+
+    // every connection task notices the shutdown on its next tick and winds down by itself.
+    drop(listener);
+    println!(
+        "[mq] shutting down, {} connection(s) open",
+        connections.len()
+    );
+    let drained = tokio::time::timeout(shutdown.timeout, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        println!(
+            "[mq] shutdown timed out, dropping {} connection(s)",
+            connections.len()
+        );
+        connections.shutdown().await;
+    }
+    println!("[mq] runtime stopped");
+    Ok(())
+}
+
+async fn connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    limits: Limits,
+    manager_proxy: Arc<RwLock<PhysicalConnectionManager>>,
+    shutdown: Arc<Shutdown>,
+) -> io::Result<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Outgoing>();
+    let conn = PhysicalConnectionFactory::new()
+        .set_local(stream.local_addr()?)
+        .set_remote(peer)
+        .set_outbox(Outbox::task(sender))
+        .set_manager_proxy(Some(manager_proxy.clone()))
+        .build()
+        .map_err(|_| io::Error::other("failed to build connection"))?;
+    let handle = conn.handle.clone();
+    let conn = Arc::new(Mutex::new(conn));
+    manager_proxy.safe_write().add(conn.clone());
+
+    let (mut reader, mut writer) = stream.into_split();
+    let mut writer_task = tokio::spawn(async move {
+        while let Some(outgoing) = receiver.recv().await {
+            match outgoing {
+                Outgoing::Data(data) => writer.write_all(&data).await?,
+                Outgoing::Close => break,
+            }
+        }
+        writer.shutdown().await
+    });
+
+    let mut assembler = FrameAssembler::new(limits);
+    let mut buf = vec![0u8; READ_CHUNK];
+    let mut tick = tokio::time::interval(TICK);
+    let mut writer_done = false;
+    let result = 'read: loop {
+        tokio::select! {
+            // the writer only stops after a close, or when the socket is broken.
+            _ = &mut writer_task => {
+                writer_done = true;
+                break 'read Ok(());
+            }
+            n = reader.read(&mut buf) => match n {
+                Ok(0) => break 'read Ok(()),
+                Ok(n) => {
+                    let frames = match assembler.feed(&buf[..n]) {
+                        Ok(frames) => frames,
+                        Err(e) => {
+                            handle.reject(e.status());
+                            break 'read Err(io::Error::other(e.to_string()));
+                        }
+                    };
+                    for frame in frames {
+                        // awaited one by one, so the frames of a connection keep their order.
+                        let conn = conn.clone();
+                        if let Err(e) =
+                            task::spawn_blocking(move || PhysicalConnection::handle_caught(&conn, frame))
+                                .await
+                        {
+                            break 'read Err(io::Error::other(e));
+                        }
+                    }
+                }
+                Err(e) => break 'read Err(e),
+            },
+            _ = tick.tick() => {
+                if shutdown.requested() {
+                    // frames are handled before the next read, so none are in flight here.
+                    conn.safe_lock().notify_shutdown();
+                    break 'read Ok(());
+                }
+                if let Ok(conn) = conn.try_lock() {
+                    if conn.expired() {
+                        conn.close_expired();
+                    }
+                }
+            }
+        }
+    };
+
+    // let the writer flush whatever is still queued, then it shuts the socket down.
+    handle.outbox.close();
+    if !writer_done {
+        writer_task.await.unwrap_or(Ok(())).unwrap_or(());
+    }
+    handle.set_closed();
+    manager_proxy.safe_write().remove(handle.id);
+    result
+}
@@ -1,9 +1,15 @@
+use crate::mq::protocol::proto::DataHead;
+
 pub struct Channel {
     pub name: String,
     receiving: u64,
     received: u64,
 
     buffer: Vec<u8>,
+
+    // multiplexed framing: the head of the message being assembled, and the slice expected next.
+    head: Option<DataHead>,
+    next_seq: u32,
 }
 
 impl Channel {
@@ -13,6 +19,8 @@ impl Channel {
             receiving: 0,
             received: 0,
             buffer: vec![],
+            head: None,
+            next_seq: 0,
         }
     }
 
@@ -35,7 +43,34 @@ impl Channel {
         self.buffer.drain(0..self.buffer.len()).collect()
     }
 
+    pub fn buffered(&self) -> u64 {
+        self.buffer.len() as u64
+    }
+
     pub fn peek_buffer(&self) -> Vec<u8> {
         self.buffer.clone()
     }
+
+    // a message still being assembled on this channel is given up.
+    pub fn begin(&mut self, head: DataHead, receiving: u64) {
+        self.buffer.clear();
+        self.received = 0;
+        self.receiving = receiving;
+        self.head = Some(head);
+        self.next_seq = 0;
+    }
+
+    pub fn expects(&self, seq: u32) -> bool {
+        self.head.is_some() && self.next_seq == seq
+    }
+
+    pub fn write_slice(&mut self, data: Vec<u8>) -> bool {
+        self.next_seq += 1;
+        let size = data.len() as u64;
+        self.write_buffer(data, size)
+    }
+
+    pub fn take_head(&mut self) -> Option<DataHead> {
+        self.head.take()
+    }
 }
@@ -1,42 +1,60 @@
+use crate::mq::auth::manager::{AuthManager, Principal};
+use crate::mq::auth::perm::Permission;
+use crate::mq::common::sync::{SafeLock, SafeRwLock};
 use crate::mq::host::manager::HostManager;
-use crate::mq::host::vhost::VirtualHost;
-use crate::mq::net::chan::Channel;
-use crate::mq::net::manager::{ChannelManager, PhysicalConnectionManager};
-use crate::mq::protocol::proto::DataHead;
-use crate::mq::protocol::protobase::{Deserialize, Serialize};
+use crate::mq::net::frame::Frame;
+use crate::mq::net::manager::PhysicalConnectionManager;
+use crate::mq::net::registry::{ConnectionHandle, MAX_SLICE_SIZE};
+use crate::mq::protocol::proto::{reply_head, DataHead};
+use crate::mq::protocol::protobase::Deserialize;
 use crate::mq::protocol::raw::{IOType, Raw, RawCommand, RawData, RawMessage};
-use crate::mq::routing::key::RoutingKey;
+use crate::mq::protocol::status;
+use crate::mq::queue::queue_object::QueueObject;
+use crate::mq::routing::headers::split_headers;
+use crate::mq::routing::key::{RoutingKey, RoutingPath};
 use std::cell::RefCell;
-use std::error::Error;
-use std::io::ErrorKind::UnexpectedEof;
-use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpStream};
+use std::net::SocketAddr;
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::sync::{Arc, Mutex, RwLock};
-use std::thread;
 
+// the protocol side of a client connection. the socket itself is owned by the reactor,
+// which hands complete frames to a worker; replies go back through the outbox.
 pub struct PhysicalConnection {
     pub local_addr: SocketAddr,
     pub remote_addr: SocketAddr,
 
-    pub stream: RefCell<TcpStream>,
-    pub closed: RefCell<bool>,
+    pub handle: Arc<ConnectionHandle>,
+    pub principal: RefCell<Option<Principal>>,
 
     pub manager_proxy: Arc<RwLock<PhysicalConnectionManager>>,
-    pub channel_manager: RefCell<ChannelManager>,
+    // taken from the manager when the connection is built,
+    // so frames never have to go through the connection manager's lock.
+    pub host_manager: Option<Arc<RwLock<HostManager>>>,
+    pub auth_manager: Option<Arc<AuthManager>>,
 }
 
 impl PhysicalConnection {
-    pub fn launch(self) -> Arc<Mutex<PhysicalConnection>> {
-        let s0 = Arc::new(Mutex::new(self));
-        let s1 = s0.clone();
-        thread::spawn(move || {
-            s1.lock().unwrap().listen().unwrap();
-        });
-        s0
-    }
-
-    fn process(&self, data_head: &DataHead, buffer: Vec<u8>) -> RawData {
+    // None for a frame that can not be routed.
+    fn process(&self, data_head: &DataHead, buffer: Vec<u8>) -> Option<RawData> {
         let mut io_type = IOType::Write;
+        // with the fourth byte of routing_mod set, the exchange path comes as a line
+        // in front of the body instead of in the first two route slots.
+        let (long_path, buffer) = match data_head.routing_mod[3] {
+            1u8 => match RoutingPath::split_exchanges(buffer) {
+                Some((exchanges, buffer)) => (Some(exchanges), buffer),
+                None => {
+                    println!("[mq] malformed frame: long path without a path line");
+                    return None;
+                }
+            },
+            _ => (None, buffer),
+        };
+        // a headers route carries its headers in front of the body, after the path.
+        let (headers, buffer) = match data_head.routing_mod[2] {
+            3u8 => split_headers(buffer),
+            _ => (vec![], buffer),
+        };
 
         let channel = String::from_utf8(data_head.channel.clone().to_vec())
             .unwrap()
@@ -62,6 +80,8 @@ impl PhysicalConnection {
                         io_type = IOType::Read;
                         RawMessage::Fetch(buffer)
                     }
+                    2u8 => RawMessage::Subscribe(buffer),
+                    3u8 => RawMessage::Cancel(buffer),
                     _ => {
                         // dbg!("nop");
                         RawMessage::Nop
@@ -97,6 +117,7 @@ impl PhysicalConnection {
                         // dbg!("drop binding");
                         RawCommand::DropBinding(buffer)
                     }
+                    6u8 => RawCommand::ListBindings,
                     _ => {
                         // dbg!("nop");
                         RawCommand::Nop
@@ -129,6 +150,10 @@ impl PhysicalConnection {
                 .trim_end_matches("\0")
                 .to_string(),
         ];
+        let mut routing_arr = RoutingPath::from(routing_arr);
+        if let Some(exchanges) = long_path {
+            routing_arr.exchanges = exchanges;
+        }
 
         // match third byte of routing_mod as routing type
         let routing: RoutingKey = match data_head.routing_mod[2] {
@@ -144,200 +169,209 @@ impl PhysicalConnection {
                 // dbg!("fanout");
                 RoutingKey::Fanout(routing_arr)
             }
+            3u8 => RoutingKey::Headers(routing_arr, headers),
             _ => {
                 // dbg!("nop");
                 RoutingKey::Direct(routing_arr)
             }
         };
 
-        RawData {
+        Some(RawData {
             raw,
             channel: channel.clone(),
             virtual_host: virtual_host.clone(),
             routing_key: routing,
             io_type,
-        }
+            content_type: data_head.content_type,
+            peer_addr: Some(self.remote_addr),
+            connection: Some(self.handle.clone()),
+        })
     }
 
-    fn get_host_manager_proxy(&self, io_type: &IOType) -> Arc<RwLock<HostManager>> {
-        match io_type {
-            IOType::Read => {
-                // dbg!("read");
-                self.manager_proxy
-                    .clone()
-                    .read()
-                    .unwrap()
-                    .host_manager
-                    .clone()
-                    .unwrap()
-            }
-            IOType::Write => {
-                // dbg!("write");
-                self.manager_proxy
-                    .clone()
-                    .write()
-                    .unwrap()
-                    .host_manager
-                    .clone()
-                    .unwrap()
+    fn requires_login(&self) -> bool {
+        self.principal.borrow().is_none()
+            && self
+                .auth_manager
+                .as_ref()
+                .is_some_and(|auth| auth.enabled())
+    }
+
+    fn login(&self, cmd: &str, buffer: &[u8]) -> Result<(), u16> {
+        match &self.auth_manager {
+            Some(auth) if auth.enabled() => {
+                let principal = match cmd {
+                    "LOGIN" => auth.login_plain(buffer)?,
+                    "LOGIN-TOKEN" => auth.login_token(buffer)?,
+                    _ => return Err(status::AUTH_REQUIRED),
+                };
+                println!(
+                    "[mq] user {} logged in from {}",
+                    principal.user, self.remote_addr
+                );
+                self.handle.set_user(principal.user.clone());
+                self.principal.replace(Some(principal));
+                Ok(())
             }
+            _ => Ok(()),
         }
     }
 
-    pub fn listen(&self) -> Result<(), Box<dyn Error>> {
-        'listen: loop {
-            let mut buf = [0u8; 256];
-            self.stream.borrow_mut().set_nodelay(false)?;
-            let n = self.stream.borrow_mut().read_exact(&mut buf);
-            match n {
-                Ok(_) => {
-                    // dbg!("incoming transmission!");
-                    self.stream.borrow_mut().flush().unwrap_or(());
+    pub fn expired(&self) -> bool {
+        self.principal
+            .borrow()
+            .as_ref()
+            .is_some_and(|principal| principal.expired())
+    }
 
-                    let head = DataHead::deserialize(buf);
-                    let channel = String::from_utf8(head.channel.clone().to_vec())?;
-                    if !self.channel_manager.borrow_mut().contains(&channel) {
-                        let ch = Channel::new(channel.clone());
-                        self.channel_manager.borrow_mut().add(ch);
-                    }
+    pub fn close_expired(&self) {
+        println!("[mq] token expired: {}", self.remote_addr);
+        let head = DataHead::deserialize([0u8; 256]);
+        self.send_feedback(&head, Vec::new(), status::TOKEN_EXPIRED);
+        self.close();
+    }
 
-                    let size = u64::from(head.slice_size);
-                    // note that the head is not included when calculating 'count'.
-                    let count = u64::from(head.slice_count);
+    // tells the client the broker is going away, frames already sent are still handled.
+    pub fn notify_shutdown(&self) {
+        if !self.handle.closing() && !self.handle.closed() {
+            let head = DataHead::deserialize([0u8; 256]);
+            self.send_feedback(&head, Vec::new(), status::SHUTTING_DOWN);
+        }
+    }
 
-                    let mut channel_manager = self.channel_manager.borrow_mut();
-                    let channel = channel_manager.get(&channel.clone()).unwrap();
+    pub fn close(&self) {
+        self.handle.close();
+    }
 
-                    channel.set_receiving(u64::from(size) * u64::from(count));
-                    // slice1(data_head, data[size]), slice2(data_head, data[size]), ...
+    fn authorize(&self, raw: &RawData) -> bool {
+        let principal = self.principal.borrow();
+        let principal = match principal.as_ref() {
+            Some(principal) => principal,
+            None => return true,
+        };
+        for (permission, path) in Permission::required(raw) {
+            if !principal.authorize(&raw.virtual_host, permission, &path) {
+                println!(
+                    "[mq] access refused: {} may not {:?} {} in {}",
+                    principal.user, permission, path, raw.virtual_host
+                );
+                return false;
+            }
+        }
+        true
+    }
 
-                    let mut completed = false || size * count == 0; // if the package doesn't have body.
-                    const SLICE_SIZE: u64 = 256;
-                    // dbg!(size, count);
-                    for _ in 0..(size * count / SLICE_SIZE) {
-                        self.stream.borrow_mut().flush().unwrap_or(());
-                        let mut buf = [0u8; SLICE_SIZE as usize];
-                        'read: loop {
-                            match self.stream.borrow_mut().read_exact(&mut buf) {
-                                Ok(_) => {
-                                    // dbg!("read!", i);
-                                    // dbg!("buf: ", String::from_utf8(buf.to_vec()).unwrap());
-                                    completed = channel.write_buffer(Vec::from(&buf), SLICE_SIZE);
-                                    //// dbg!(channel.peek_buffer().last().unwrap());
-                                    // dbg!(channel.peek_buffer().len() / 256);
-                                    break 'read;
-                                }
-                                Err(e) => {
-                                    if e.kind() == UnexpectedEof {
-                                        // dbg!("conn closed.");
-                                        self.closed.replace(true);
-                                        break 'listen;
-                                    }
-                                }
-                            }
-                        }
-                    }
+    fn send_feedback(&self, head: &DataHead, buffer: Vec<u8>, err_handle: u16) {
+        self.handle
+            .send(self.feedback_head(head, err_handle), &buffer);
+    }
 
-                    if completed {
-                        // always remember that the last value of RoutingKey is the name of the Queue.
-                        let buf = channel.read_buffer();
-                        let raw = self.process(&head, buf);
-                        let host = VirtualHost::new(raw.virtual_host.clone());
+    // a fetched message goes back with the content type it was pushed with.
+    fn send_object(&self, head: &DataHead, obj: QueueObject, err_handle: u16) {
+        let mut data_head = self.feedback_head(head, err_handle);
+        data_head.content_type = obj.content_type;
+        self.handle.send(data_head, &obj.content);
+    }
 
-                        let io_type = &raw.io_type;
-                        let mut err_handle: u16 = 0;
+    fn feedback_head(&self, head: &DataHead, err_handle: u16) -> DataHead {
+        // replies are cut into slices of the size the request came in with, up to a maximum.
+        let slice_size = head.slice_size.min(MAX_SLICE_SIZE);
+        reply_head(&head.virtual_host, head.channel, slice_size, err_handle)
+    }
 
-                        let result = self
-                            .get_host_manager_proxy(io_type)
-                            .read()
-                            .unwrap()
-                            .send_raw_to_host(raw, &mut err_handle);
+    // a panic while handling a frame only takes down the connection it happened on.
+    // locks it poisoned are recovered by the next one to take them, queues keep their contents.
+    pub fn handle_caught(conn: &Mutex<PhysicalConnection>, frame: Frame) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| conn.safe_lock().handle(frame)));
+        if let Err(payload) = result {
+            let conn = conn.safe_lock();
+            let reason = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            println!(
+                "[mq] connection {} panicked, closing it: {}",
+                conn.handle.id, reason
+            );
+            conn.handle.reject(status::INTERNAL_ERROR);
+        }
+    }
 
-                        // todo: I see no difference whether to use read() or write().
-                        // but that remains to be tested.
-                        /*match io_type {
-                            IOType::Read => {
-                                self.get_host_manager_proxy(io_type)
-                                    .read()
-                                    .unwrap()
-                                    .send_raw_to_host(raw)
-                            }
-                            IOType::Write => {
-                                self.get_host_manager_proxy(io_type)
-                                    .write()
-                                    .unwrap()
-                                    .send_raw_to_host(raw)
-                            }
-                        };*/
+    // called on a worker thread for every frame the reactor has assembled.
+    pub fn handle(&self, frame: Frame) {
+        // frames that were already in flight when the peer hung up are still handled.
+        if self.handle.closing() {
+            return;
+        }
+        if self.expired() {
+            self.close_expired();
+            return;
+        }
 
-                        // seems that when lock is acquired here, send_raw_data() can't use it, causing deadlock.
-                        if let Some(feedback) = result {
-                            let mut buffer = feedback.content;
-                            if buffer.len() % 256 != 0 || buffer.len() == 0 {
-                                // dbg!("buffer size not aligned to 256 bytes!", buffer.len());
-                                let align = 256 - buffer.len() % 256;
-                                for _ in 0..align {
-                                    buffer.push(0u8);
-                                }
-                            }
+        let cmd = frame.command();
+        let head = frame.head;
+        let mut buf = frame.body;
+        // clients that announce the body length get their padding cut off here.
+        if head.count > 0 && head.count as usize <= buf.len() {
+            buf.truncate(head.count as usize);
+        }
+        self.handle.set_slice_size(head.slice_size);
 
-                            // send feedback.
-                            let channel = head.channel.clone();
-                            let slice_count = buffer.len() / 256;
-                            let data_head = DataHead::new(
-                                host,
-                                channel,
-                                [0u8; 4],
-                                [0u8; 24],
-                                [0u8; 128],
-                                slice_count as u32,
-                                SLICE_SIZE as u32,
-                                0,
-                                err_handle,
-                            );
-                            // println!("sending feedback: {:?}", data_head);
-                            let mut head_serialized = data_head.serialize_vec();
-                            head_serialized.append(&mut buffer);
-                            let concatenated = head_serialized;
-                            self.stream
-                                .borrow_mut()
-                                .write_all(concatenated.as_slice())
-                                .unwrap();
-                        }
-                    }
+        // the assembler already switched to multiplexed framing, this only confirms it.
+        if cmd == "MUX" {
+            self.send_feedback(&head, Vec::new(), status::OK);
+            return;
+        }
 
-                    let channel_name = channel.name.clone();
-                    let command = String::from_utf8(head.command.to_vec()).unwrap();
-                    drop(channel_manager);
+        // clients may name themselves before logging in, the name shows up in the registry.
+        if cmd == "HELLO" {
+            let client_name = String::from_utf8_lossy(&buf)
+                .trim_end_matches("\0")
+                .trim()
+                .to_string();
+            self.handle.set_client_name(client_name);
+            self.send_feedback(&head, Vec::new(), status::OK);
+            return;
+        }
 
-                    // todo: (IMPORTANT) add logic for closing the channel using attr 'command'
-                    let cmd = command
-                        .to_uppercase()
-                        .as_str()
-                        .trim_end_matches("\0")
-                        .to_string();
-                    // dbg!(&cmd);
-                    match cmd.as_str() {
-                        "CLOSE-CH" => {
-                            // dbg!("close channel");
-                            self.channel_manager
-                                .borrow_mut()
-                                .remove(channel_name.as_str());
-                        }
-                        _ => {
-                            // dbg!("nop(cmd)");
-                        }
-                    }
-                }
-                Err(e) => {
-                    if e.kind() == UnexpectedEof {
-                        // dbg!("conn closed.");
-                        self.closed.replace(true);
-                        break 'listen;
-                    }
-                }
+        if cmd == "LOGIN" || cmd == "LOGIN-TOKEN" || self.requires_login() {
+            // no frame may reach the host layer before the login has succeeded.
+            let err_handle = match self.login(&cmd, &buf) {
+                Ok(_) => status::OK,
+                Err(code) => code,
+            };
+            self.send_feedback(&head, Vec::new(), err_handle);
+            if err_handle != status::OK {
+                println!("[mq] login rejected: {}", self.remote_addr);
+                self.close();
+            }
+            return;
+        }
+
+        // always remember that the last value of RoutingKey is the name of the Queue.
+        let raw = match self.process(&head, buf) {
+            Some(raw) => raw,
+            None => {
+                self.send_feedback(&head, Vec::new(), status::BAD_REQUEST);
+                return;
             }
+        };
+        if !self.authorize(&raw) {
+            self.send_feedback(&head, Vec::new(), status::ACCESS_REFUSED);
+            return;
+        }
+
+        let host_manager = match &self.host_manager {
+            Some(host_manager) => host_manager,
+            None => return,
+        };
+        let mut err_handle: u16 = 0;
+        let result = host_manager
+            .safe_read()
+            .send_raw_to_host(raw, &mut err_handle);
+
+        if let Some(feedback) = result {
+            self.send_object(&head, feedback, err_handle);
         }
-        Ok(())
     }
 }
@@ -1,13 +1,18 @@
+use crate::mq::common::sync::SafeRwLock;
 use crate::mq::net::conn::PhysicalConnection;
-use crate::mq::net::manager::{ChannelManager, PhysicalConnectionManager};
+use crate::mq::net::manager::PhysicalConnectionManager;
+use crate::mq::net::outbox::Outbox;
+use crate::mq::net::registry::ConnectionHandle;
+use mio::net::TcpStream;
 use std::cell::RefCell;
-use std::net::{SocketAddr, TcpStream};
+use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 
 pub struct PhysicalConnectionFactory {
     local: Option<SocketAddr>,
     remote: Option<SocketAddr>,
     stream: Option<TcpStream>,
+    outbox: Option<Outbox>,
     manager_proxy: Option<Arc<RwLock<PhysicalConnectionManager>>>,
 }
 
@@ -17,6 +22,7 @@ impl PhysicalConnectionFactory {
             local: None,
             remote: None,
             stream: None,
+            outbox: None,
             manager_proxy: None,
         }
     }
@@ -44,34 +50,44 @@ impl PhysicalConnectionFactory {
         self
     }
 
-    pub fn fetch(mut self) -> Result<PhysicalConnection, ()> {
-        if let Some(rem) = self.remote {
-            let conn = TcpStream::connect(rem).unwrap();
-            self.local = Some(conn.local_addr().unwrap().clone());
-            self.remote = Some(conn.peer_addr().unwrap().clone());
-            Ok(PhysicalConnection {
-                local_addr: self.local.unwrap(),
-                remote_addr: self.remote.unwrap(),
-                stream: RefCell::from(conn),
-                closed: RefCell::from(false),
+    pub fn set_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
 
-                manager_proxy: self.manager_proxy.unwrap(),
-                channel_manager: RefCell::from(ChannelManager::new()),
-            })
-        } else if let Some(conn) = self.stream {
-            self.local = Some(conn.local_addr().unwrap().clone());
-            self.remote = Some(conn.peer_addr().unwrap().clone());
-            Ok(PhysicalConnection {
-                local_addr: self.local.unwrap(),
-                remote_addr: self.remote.unwrap(),
-                stream: RefCell::from(conn),
-                closed: RefCell::from(false),
+    // builds the connection from addresses that are already known, without touching a socket.
+    pub fn build(self) -> Result<PhysicalConnection, ()> {
+        let remote_addr = self.remote.ok_or(())?;
+        let manager_proxy = self.manager_proxy.ok_or(())?;
+        let (host_manager, auth_manager) = {
+            let manager = manager_proxy.safe_read();
+            (manager.host_manager.clone(), manager.auth_manager.clone())
+        };
+        Ok(PhysicalConnection {
+            local_addr: self.local.ok_or(())?,
+            remote_addr,
+            handle: ConnectionHandle::new(remote_addr, self.outbox.ok_or(())?),
+            principal: RefCell::from(None),
 
-                manager_proxy: self.manager_proxy.unwrap(),
-                channel_manager: RefCell::from(ChannelManager::new()),
-            })
+            manager_proxy,
+            host_manager,
+            auth_manager,
+        })
+    }
+
+    // the stream is handed back alongside the connection, it belongs to the reactor.
+    pub fn fetch(mut self) -> Result<(PhysicalConnection, TcpStream), ()> {
+        let conn = if let Some(rem) = self.remote {
+            let conn = std::net::TcpStream::connect(rem).map_err(|_| ())?;
+            conn.set_nonblocking(true).map_err(|_| ())?;
+            TcpStream::from_std(conn)
+        } else if let Some(conn) = self.stream.take() {
+            conn
         } else {
-            Err(())
-        }
+            return Err(());
+        };
+        self.local = Some(conn.local_addr().map_err(|_| ())?);
+        self.remote = Some(conn.peer_addr().map_err(|_| ())?);
+        Ok((self.build()?, conn))
     }
 }
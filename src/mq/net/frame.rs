@@ -0,0 +1,504 @@
+use crate::mq::net::chan::Channel;
+use crate::mq::net::limits::Limits;
+use crate::mq::net::manager::ChannelManager;
+use crate::mq::protocol::proto::{DataHead, SliceHead};
+use crate::mq::protocol::protobase::Deserialize;
+use crate::mq::protocol::status;
+use std::collections::VecDeque;
+use std::fmt;
+
+const HEAD_SIZE: usize = 256;
+
+// multiplexed framing: every unit on the wire starts with one of these tags.
+const TAG_HEAD: u8 = b'H';
+const TAG_SLICE: u8 = b'S';
+const SLICE_HEAD_SIZE: usize = 48;
+
+pub struct Frame {
+    pub head: DataHead,
+    pub body: Vec<u8>,
+}
+
+impl Frame {
+    pub fn command(&self) -> String {
+        command_of(&self.head)
+    }
+}
+
+fn command_of(head: &DataHead) -> String {
+    String::from_utf8_lossy(&head.command)
+        .to_uppercase()
+        .trim_end_matches("\0")
+        .to_string()
+}
+
+fn channel_of(raw: &[u8]) -> String {
+    String::from_utf8_lossy(raw).to_string()
+}
+
+// the stream can not be parsed any further, or the client went over a limit.
+// either way the connection has to go.
+#[derive(Debug)]
+pub enum FrameError {
+    Malformed(String),
+    MessageTooLarge(u64),
+    TooManyChannels(usize),
+    BufferLimit(u64),
+}
+
+impl FrameError {
+    pub fn status(&self) -> u16 {
+        match self {
+            FrameError::Malformed(_) => status::FRAME_ERROR,
+            FrameError::MessageTooLarge(_) => status::MESSAGE_TOO_LARGE,
+            FrameError::TooManyChannels(_) => status::TOO_MANY_CHANNELS,
+            FrameError::BufferLimit(_) => status::BUFFER_LIMIT,
+        }
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Malformed(reason) => write!(f, "malformed frame: {}", reason),
+            FrameError::MessageTooLarge(size) => write!(f, "message of {} bytes too large", size),
+            FrameError::TooManyChannels(max) => write!(f, "more than {} channels", max),
+            FrameError::BufferLimit(max) => write!(f, "more than {} bytes buffered", max),
+        }
+    }
+}
+
+// the head currently being received, with the number of body slices still to come.
+struct Pending {
+    head: DataHead,
+    channel: String,
+    // as announced in the head, so clients can pick bigger slices for bulk transfers.
+    slice_size: usize,
+    slices_left: u64,
+    completed: bool,
+}
+
+// non-blocking frame assembly: bytes are fed in as they arrive on the socket,
+// and complete frames come out once the head and all of its slices are in.
+//
+// a connection starts out in sequential framing, where the slices of a message follow its head.
+// after a MUX frame every unit is tagged instead: 'H' + head, or 'S' + slice head + data.
+// each channel then assembles its own message, so a small message is not stuck behind a big one.
+pub struct FrameAssembler {
+    input: Vec<u8>,
+    pending: Option<Pending>,
+    channel_manager: ChannelManager,
+    multiplexed: bool,
+    limits: Limits,
+}
+
+impl FrameAssembler {
+    pub fn new(limits: Limits) -> FrameAssembler {
+        FrameAssembler {
+            input: Vec::new(),
+            pending: None,
+            channel_manager: ChannelManager::new(),
+            multiplexed: false,
+            limits,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Result<VecDeque<Frame>, FrameError> {
+        self.input.extend_from_slice(data);
+        self.check_buffered()?;
+
+        let mut frames = VecDeque::new();
+        let mut offset = 0;
+        let result = loop {
+            let step = if self.multiplexed {
+                self.step_multiplexed(&mut offset, &mut frames)
+            } else {
+                self.step_sequential(&mut offset, &mut frames)
+            };
+            match step {
+                Ok(true) => continue,
+                Ok(false) => break Ok(frames),
+                Err(e) => break Err(e),
+            }
+        };
+        self.input.drain(0..offset);
+        self.check_buffered()?;
+        result
+    }
+
+    // the unparsed input plus everything the channels hold for messages not complete yet.
+    fn check_buffered(&self) -> Result<(), FrameError> {
+        let buffered = self.input.len() as u64
+            + self
+                .channel_manager
+                .list()
+                .iter()
+                .map(|channel| channel.buffered())
+                .sum::<u64>();
+        if buffered > self.limits.max_buffered_bytes {
+            return Err(FrameError::BufferLimit(self.limits.max_buffered_bytes));
+        }
+        Ok(())
+    }
+
+    // the slice size is checked on its own too, replies are padded to whole slices.
+    fn check_size(&self, head: &DataHead) -> Result<u64, FrameError> {
+        let size = u64::from(head.slice_size) * u64::from(head.slice_count);
+        if size > self.limits.max_message_size {
+            return Err(FrameError::MessageTooLarge(size));
+        }
+        if u64::from(head.slice_size) > self.limits.max_message_size {
+            return Err(FrameError::MessageTooLarge(u64::from(head.slice_size)));
+        }
+        Ok(size)
+    }
+
+    // returns false once more input is needed.
+    fn step_sequential(
+        &mut self,
+        offset: &mut usize,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<bool, FrameError> {
+        match self.pending.as_mut() {
+            None => {
+                if self.input.len() - *offset < HEAD_SIZE {
+                    return Ok(false);
+                }
+                let head = self.read_head(*offset);
+                *offset += HEAD_SIZE;
+                self.pending = Some(self.begin(head)?);
+            }
+            Some(pending) if pending.slices_left > 0 => {
+                let slice_size = pending.slice_size;
+                if self.input.len() - *offset < slice_size {
+                    return Ok(false);
+                }
+                let channel = self.channel_manager.get(&pending.channel).unwrap();
+                pending.completed = channel.write_buffer(
+                    self.input[*offset..*offset + slice_size].to_vec(),
+                    slice_size as u64,
+                );
+                pending.slices_left -= 1;
+                *offset += slice_size;
+            }
+            Some(_) => {
+                let pending = self.pending.take().unwrap();
+                if let Some(frame) = self.finish(pending) {
+                    frames.push_back(frame);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn step_multiplexed(
+        &mut self,
+        offset: &mut usize,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<bool, FrameError> {
+        let available = self.input.len() - *offset;
+        if available == 0 {
+            return Ok(false);
+        }
+        match self.input[*offset] {
+            TAG_HEAD => {
+                if available < 1 + HEAD_SIZE {
+                    return Ok(false);
+                }
+                let head = self.read_head(*offset + 1);
+                *offset += 1 + HEAD_SIZE;
+
+                let channel = channel_of(&head.channel);
+                let receiving = self.check_size(&head)?;
+                self.channel(&channel)?.begin(head, receiving);
+                if receiving == 0 {
+                    frames.extend(self.complete(&channel));
+                }
+            }
+            TAG_SLICE => {
+                if available < 1 + SLICE_HEAD_SIZE {
+                    return Ok(false);
+                }
+                let start = *offset + 1;
+                let slice_head = SliceHead::deserialize(
+                    <[u8; SLICE_HEAD_SIZE]>::try_from(&self.input[start..start + SLICE_HEAD_SIZE])
+                        .unwrap(),
+                );
+                let len = slice_head.len as usize;
+                if available < 1 + SLICE_HEAD_SIZE + len {
+                    return Ok(false);
+                }
+                let data =
+                    self.input[start + SLICE_HEAD_SIZE..start + SLICE_HEAD_SIZE + len].to_vec();
+                *offset += 1 + SLICE_HEAD_SIZE + len;
+
+                let channel = channel_of(&slice_head.channel);
+                let expected = self
+                    .channel_manager
+                    .get(&channel)
+                    .is_some_and(|c| c.expects(slice_head.seq));
+                if !expected {
+                    // a slice for a channel with no message underway, or past the end of one.
+                    // the client and broker disagree on the stream, so the connection has to go.
+                    return Err(FrameError::Malformed(format!(
+                        "unexpected slice {} on channel {}",
+                        slice_head.seq,
+                        channel.trim_end_matches("\0")
+                    )));
+                }
+                if self.channel(&channel)?.write_slice(data) {
+                    frames.extend(self.complete(&channel));
+                }
+            }
+            tag => {
+                return Err(FrameError::Malformed(format!("unknown tag 0x{:02x}", tag)));
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_head(&self, offset: usize) -> DataHead {
+        DataHead::deserialize(
+            <[u8; HEAD_SIZE]>::try_from(&self.input[offset..offset + HEAD_SIZE]).unwrap(),
+        )
+    }
+
+    fn channel(&mut self, name: &str) -> Result<&mut Channel, FrameError> {
+        if !self.channel_manager.contains(name) {
+            if self.channel_manager.len() >= self.limits.max_channels {
+                return Err(FrameError::TooManyChannels(self.limits.max_channels));
+            }
+            self.channel_manager.add(Channel::new(name.to_string()));
+        }
+        Ok(self.channel_manager.get(name).unwrap())
+    }
+
+    fn begin(&mut self, head: DataHead) -> Result<Pending, FrameError> {
+        let channel = channel_of(&head.channel);
+
+        // note that the head is not included when calculating 'count'.
+        let size = self.check_size(&head)?;
+        self.channel(&channel)?.set_receiving(size);
+        // slice1(data_head, data[size]), slice2(data_head, data[size]), ...
+
+        Ok(Pending {
+            slice_size: head.slice_size as usize,
+            slices_left: if size == 0 {
+                0
+            } else {
+                u64::from(head.slice_count)
+            },
+            head,
+            channel,
+            completed: size == 0, // if the package doesn't have body.
+        })
+    }
+
+    fn finish(&mut self, pending: Pending) -> Option<Frame> {
+        let channel = self.channel_manager.get(&pending.channel).unwrap();
+        let frame = if pending.completed {
+            Some(Frame {
+                head: pending.head,
+                body: channel.read_buffer(),
+            })
+        } else {
+            None
+        };
+        self.after(frame, &pending.channel)
+    }
+
+    fn complete(&mut self, name: &str) -> Option<Frame> {
+        let channel = self.channel_manager.get(name)?;
+        let frame = Frame {
+            head: channel.take_head()?,
+            body: channel.read_buffer(),
+        };
+        self.after(Some(frame), name)
+    }
+
+    // framing commands take effect right away, for the bytes that follow in the same read.
+    fn after(&mut self, frame: Option<Frame>, channel: &str) -> Option<Frame> {
+        let command = frame.as_ref().map(|frame| frame.command());
+        match command.as_deref() {
+            Some("CLOSE-CH") => {
+                self.channel_manager.remove(channel);
+            }
+            Some("MUX") => self.multiplexed = true,
+            _ => {}
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mq::protocol::protobase::Serialize;
+
+    fn head(channel: &str, command: &str, slice_count: u32, slice_size: u32) -> DataHead {
+        let mut head = DataHead::deserialize([0u8; HEAD_SIZE]);
+        head.channel[..channel.len()].copy_from_slice(channel.as_bytes());
+        head.command[..command.len()].copy_from_slice(command.as_bytes());
+        head.slice_count = slice_count;
+        head.slice_size = slice_size;
+        head
+    }
+
+    fn limited(max_message_size: u64, max_channels: usize, max_buffered_bytes: u64) -> Limits {
+        Limits {
+            max_message_size,
+            max_channels,
+            max_buffered_bytes,
+        }
+    }
+
+    #[test]
+    fn sequential_frames_are_assembled() {
+        let mut assembler = FrameAssembler::new(Limits::new());
+        let mut input = head("a", "PUSH", 2, 256).serialize().to_vec();
+        input.extend_from_slice(&[b'a'; 512]);
+        input.extend(head("a", "FETCH", 0, 256).serialize());
+
+        let frames = assembler.feed(&input).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].command(), "PUSH");
+        assert_eq!(frames[0].body, [b'a'; 512]);
+        assert_eq!(frames[1].command(), "FETCH");
+        assert!(frames[1].body.is_empty());
+    }
+
+    #[test]
+    fn slices_follow_the_slice_size_of_the_head() {
+        let mut assembler = FrameAssembler::new(Limits::new());
+        let mut input = head("a", "PUSH", 2, 4).serialize().to_vec();
+        input.extend_from_slice(b"aaaabbbb");
+        let frames = assembler.feed(&input).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].body, b"aaaabbbb");
+    }
+
+    #[test]
+    fn message_over_the_size_limit_is_rejected() {
+        let mut assembler = FrameAssembler::new(limited(16, 8, 1024));
+        assert!(assembler.feed(&head("a", "PUSH", 4, 4).serialize()).is_ok());
+        let mut assembler = FrameAssembler::new(limited(16, 8, 1024));
+        let result = assembler.feed(&head("a", "PUSH", 5, 4).serialize());
+        assert!(matches!(result, Err(FrameError::MessageTooLarge(20))));
+
+        // a huge slice size is refused on its own, even without slices following.
+        let mut assembler = FrameAssembler::new(limited(16, 8, 1024));
+        let result = assembler.feed(&head("a", "PUSH", 0, 32).serialize());
+        assert!(matches!(result, Err(FrameError::MessageTooLarge(32))));
+    }
+
+    #[test]
+    fn channels_over_the_limit_are_rejected() {
+        let mut assembler = FrameAssembler::new(limited(1024, 2, 4096));
+        assert!(assembler.feed(&head("a", "PING", 0, 4).serialize()).is_ok());
+        assert!(assembler.feed(&head("b", "PING", 0, 4).serialize()).is_ok());
+        assert!(assembler.feed(&head("a", "PING", 0, 4).serialize()).is_ok());
+        let result = assembler.feed(&head("c", "PING", 0, 4).serialize());
+        assert!(matches!(result, Err(FrameError::TooManyChannels(2))));
+    }
+
+    #[test]
+    fn input_over_the_buffer_limit_is_rejected() {
+        let mut assembler = FrameAssembler::new(limited(1024, 8, 300));
+        let mut input = head("a", "PUSH", 16, 64).serialize().to_vec();
+        input.extend_from_slice(&[0u8; 32]);
+        assert!(assembler.feed(&input).is_ok());
+        // the rest of the message arriving at once is more than may be held.
+        let result = assembler.feed(&[0u8; 320]);
+        assert!(matches!(result, Err(FrameError::BufferLimit(300))));
+        assert_eq!(FrameError::BufferLimit(300).status(), status::BUFFER_LIMIT);
+    }
+
+    fn tagged_head(head: &DataHead) -> Vec<u8> {
+        let mut unit = vec![TAG_HEAD];
+        unit.extend(head.serialize());
+        unit
+    }
+
+    fn tagged_slice(channel: &str, seq: u32, data: &[u8]) -> Vec<u8> {
+        let mut name = [0u8; 32];
+        name[..channel.len()].copy_from_slice(channel.as_bytes());
+        let mut unit = vec![TAG_SLICE];
+        unit.extend(SliceHead::new(name, seq, data.len() as u32).serialize());
+        unit.extend_from_slice(data);
+        unit
+    }
+
+    fn multiplexed() -> FrameAssembler {
+        let mut assembler = FrameAssembler::new(Limits::new());
+        let frames = assembler.feed(&head("", "MUX", 0, 0).serialize()).unwrap();
+        assert_eq!(frames.len(), 1);
+        assembler
+    }
+
+    #[test]
+    fn interleaved_slices_are_assembled_per_channel() {
+        let mut assembler = multiplexed();
+        let mut input = tagged_head(&head("a", "PUSH", 2, 4));
+        input.extend(tagged_head(&head("b", "PUSH", 1, 4)));
+        input.extend(tagged_slice("a", 0, b"aaaa"));
+        input.extend(tagged_slice("b", 0, b"bbbb"));
+        input.extend(tagged_slice("a", 1, b"cccc"));
+
+        let frames = assembler.feed(&input).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            channel_of(&frames[0].head.channel).trim_end_matches('\0'),
+            "b"
+        );
+        assert_eq!(frames[0].body, b"bbbb");
+        assert_eq!(
+            channel_of(&frames[1].head.channel).trim_end_matches('\0'),
+            "a"
+        );
+        assert_eq!(frames[1].body, b"aaaacccc");
+    }
+
+    #[test]
+    fn units_split_across_reads_are_assembled() {
+        let mut assembler = multiplexed();
+        let mut input = tagged_head(&head("a", "PUSH", 1, 4));
+        input.extend(tagged_slice("a", 0, b"data"));
+
+        let (first, second) = input.split_at(100);
+        assert!(assembler.feed(first).unwrap().is_empty());
+        let frames = assembler.feed(second).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].body, b"data");
+    }
+
+    #[test]
+    fn slice_for_unknown_channel_is_rejected() {
+        let mut assembler = multiplexed();
+        assert!(matches!(
+            assembler.feed(&tagged_slice("x", 0, b"data")),
+            Err(FrameError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn slice_past_the_end_of_a_message_is_rejected() {
+        let mut assembler = multiplexed();
+        let mut input = tagged_head(&head("a", "PUSH", 1, 4));
+        input.extend(tagged_slice("a", 0, b"data"));
+        assert_eq!(assembler.feed(&input).unwrap().len(), 1);
+
+        assert!(matches!(
+            assembler.feed(&tagged_slice("a", 1, b"more")),
+            Err(FrameError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_tag_is_rejected() {
+        let mut assembler = multiplexed();
+        assert!(matches!(
+            assembler.feed(b"X"),
+            Err(FrameError::Malformed(_))
+        ));
+    }
+}
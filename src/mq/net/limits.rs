@@ -0,0 +1,25 @@
+// per-connection limits on what a client may make the broker hold in memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // body size of a single message, as announced by slice_count * slice_size.
+    pub max_message_size: u64,
+    pub max_channels: usize,
+    // bytes read from the socket that are not handed to a worker yet.
+    pub max_buffered_bytes: u64,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_message_size: 16 * 1024 * 1024,
+            max_channels: 256,
+            max_buffered_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
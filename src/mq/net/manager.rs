@@ -1,25 +1,30 @@
+use crate::mq::auth::manager::AuthManager;
 use crate::mq::breaker::core::Breaker;
+use crate::mq::common::sync::SafeLock;
 use crate::mq::host::manager::HostManager;
 use crate::mq::net::chan::Channel;
 use crate::mq::net::conn::PhysicalConnection;
+use crate::mq::net::registry::{ConnectionHandle, ConnectionInfo};
 use crate::mq::protocol::raw::RawData;
 use crate::mq::queue::queue_object::QueueObject;
 use std::collections::HashMap;
-use std::net::Shutdown;
 use std::sync::{Arc, Mutex, RwLock};
 
 pub struct PhysicalConnectionManager {
     breaker: Option<Arc<Mutex<Breaker>>>,
-    connections: Vec<Arc<Mutex<PhysicalConnection>>>,
+    // every open connection by id, removed again once its socket is closed.
+    connections: HashMap<u64, (Arc<Mutex<PhysicalConnection>>, Arc<ConnectionHandle>)>,
     pub host_manager: Option<Arc<RwLock<HostManager>>>,
+    pub auth_manager: Option<Arc<AuthManager>>,
 }
 
 impl PhysicalConnectionManager {
     pub fn new() -> Self {
         PhysicalConnectionManager {
             breaker: None,
-            connections: Vec::new(),
+            connections: HashMap::new(),
             host_manager: None,
+            auth_manager: None,
         }
     }
 
@@ -33,35 +38,63 @@ impl PhysicalConnectionManager {
         self
     }
 
-    pub fn add(&mut self, conn: PhysicalConnection) -> &mut Self {
-        self.connections.push(conn.launch());
+    pub fn set_auth_manager(mut self, auth_manager: Arc<AuthManager>) -> Self {
+        self.auth_manager = Some(auth_manager);
         self
     }
 
-    pub fn remove(&mut self, conn: Arc<Mutex<PhysicalConnection>>) -> &mut Self {
-        self.connections.remove(
-            self.connections
-                .iter()
-                .position(|x| Arc::ptr_eq(x, &conn))
-                .unwrap(),
+    pub fn add(&mut self, conn: Arc<Mutex<PhysicalConnection>>) -> &mut Self {
+        let handle = conn.safe_lock().handle.clone();
+        println!(
+            "[mq] connection {} opened from {}",
+            handle.id, handle.peer_addr
         );
+        self.connections.insert(handle.id, (conn, handle));
         self
     }
 
+    pub fn remove(&mut self, id: u64) -> &mut Self {
+        if self.connections.remove(&id).is_some() {
+            println!("[mq] connection {} closed", id);
+        }
+        self
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Mutex<PhysicalConnection>>> {
+        self.connections.get(&id).map(|(conn, _)| conn.clone())
+    }
+
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let mut infos: Vec<ConnectionInfo> = self
+            .connections
+            .values()
+            .map(|(_, handle)| handle.info())
+            .collect();
+        infos.sort_by_key(|info| info.id);
+        infos
+    }
+
+    // the connection is deregistered by its runtime once the socket is actually gone.
+    pub fn force_close(&self, id: u64) -> bool {
+        match self.connections.get(&id) {
+            Some((_, handle)) => {
+                println!("[mq] force closing connection {}", id);
+                handle.close()
+            }
+            None => false,
+        }
+    }
+
     pub fn send_raw_data(&self, raw_data: RawData, err_handle: &mut u16) -> Option<QueueObject> {
         self.breaker
             .clone()?
-            .lock()
-            .unwrap()
+            .safe_lock()
             .send_raw_to_host(raw_data, err_handle)
     }
 
     pub fn close(&self) {
-        for conn in &self.connections {
-            let c = conn.lock().unwrap();
-            if !c.closed.clone().clone().take() {
-                c.stream.borrow_mut().shutdown(Shutdown::Both).unwrap()
-            }
+        for (_, handle) in self.connections.values() {
+            handle.close();
         }
     }
 }
@@ -96,4 +129,12 @@ impl ChannelManager {
     pub fn contains(&self, name: &str) -> bool {
         self.channels.contains_key(name)
     }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
 }
@@ -1,4 +1,13 @@
+pub mod acl;
+#[cfg(feature = "tokio")]
+pub mod async_rt;
 pub mod chan;
 pub mod conn;
 pub mod factory;
+pub mod frame;
+pub mod limits;
 pub mod manager;
+pub mod outbox;
+pub mod reactor;
+pub mod registry;
+pub mod worker;
@@ -0,0 +1,73 @@
+use mio::{Token, Waker};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+pub enum Outgoing {
+    Data(Vec<u8>),
+    Close,
+}
+
+#[derive(Clone)]
+enum Sink {
+    // the mio reactor, woken up to flush the bytes.
+    Reactor {
+        token: Token,
+        sender: Sender<(Token, Outgoing)>,
+        waker: Arc<Waker>,
+    },
+    // the writer task of a connection in the tokio runtime.
+    #[cfg(feature = "tokio")]
+    Task(tokio::sync::mpsc::UnboundedSender<Outgoing>),
+}
+
+// handle for writing to a connection from outside the thread or task that owns its socket.
+#[derive(Clone)]
+pub struct Outbox {
+    sink: Sink,
+}
+
+impl Outbox {
+    pub fn reactor(token: Token, sender: Sender<(Token, Outgoing)>, waker: Arc<Waker>) -> Outbox {
+        Outbox {
+            sink: Sink::Reactor {
+                token,
+                sender,
+                waker,
+            },
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn task(sender: tokio::sync::mpsc::UnboundedSender<Outgoing>) -> Outbox {
+        Outbox {
+            sink: Sink::Task(sender),
+        }
+    }
+
+    pub fn send(&self, data: Vec<u8>) {
+        self.push(Outgoing::Data(data));
+    }
+
+    // the connection is closed once everything queued before has been written.
+    pub fn close(&self) {
+        self.push(Outgoing::Close);
+    }
+
+    fn push(&self, outgoing: Outgoing) {
+        match &self.sink {
+            Sink::Reactor {
+                token,
+                sender,
+                waker,
+            } => {
+                if sender.send((*token, outgoing)).is_ok() {
+                    waker.wake().unwrap_or(());
+                }
+            }
+            #[cfg(feature = "tokio")]
+            Sink::Task(sender) => {
+                sender.send(outgoing).unwrap_or(());
+            }
+        }
+    }
+}
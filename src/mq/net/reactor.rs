@@ -0,0 +1,377 @@
+use crate::mq::breaker::shutdown::Shutdown;
+use crate::mq::common::sync::SafeRwLock;
+use crate::mq::net::acl::NetworkAcl;
+use crate::mq::net::conn::PhysicalConnection;
+use crate::mq::net::factory::PhysicalConnectionFactory;
+use crate::mq::net::frame::FrameAssembler;
+use crate::mq::net::limits::Limits;
+use crate::mq::net::manager::PhysicalConnectionManager;
+use crate::mq::net::outbox::{Outbox, Outgoing};
+use crate::mq::net::registry::ConnectionHandle;
+use crate::mq::net::worker::WorkerPool;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
+use std::time::{Duration, Instant};
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(1);
+const FIRST_CONNECTION: usize = 2;
+
+// how often sessions are checked for expired tokens.
+pub const TICK: Duration = Duration::from_millis(250);
+pub const READ_CHUNK: usize = 64 * 1024;
+
+struct Socket {
+    stream: TcpStream,
+    conn: Arc<Mutex<PhysicalConnection>>,
+    handle: Arc<ConnectionHandle>,
+    assembler: FrameAssembler,
+    outgoing: Vec<u8>,
+    closing: bool,
+}
+
+pub struct Reactor {
+    poll: Poll,
+    listener: TcpListener,
+    sockets: HashMap<Token, Socket>,
+    next_token: usize,
+
+    waker: Arc<Waker>,
+    sender: Sender<(Token, Outgoing)>,
+    receiver: Receiver<(Token, Outgoing)>,
+
+    workers: WorkerPool,
+    acl: NetworkAcl,
+    limits: Limits,
+    manager_proxy: Arc<RwLock<PhysicalConnectionManager>>,
+
+    shutdown: Arc<Shutdown>,
+    // set once shutdown has begun, nothing is read or accepted after that.
+    deadline: Option<Instant>,
+    // connections not yet told about the shutdown because a worker held them.
+    unnotified: Vec<Token>,
+}
+
+impl Reactor {
+    pub fn new(
+        listener: std::net::TcpListener,
+        acl: NetworkAcl,
+        limits: Limits,
+        manager_proxy: Arc<RwLock<PhysicalConnectionManager>>,
+        workers: usize,
+        shutdown: Arc<Shutdown>,
+    ) -> io::Result<Reactor> {
+        let poll = Poll::new()?;
+        listener.set_nonblocking(true)?;
+        let mut listener = TcpListener::from_std(listener);
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        shutdown.register(waker.clone());
+        let (sender, receiver) = channel();
+
+        Ok(Reactor {
+            poll,
+            listener,
+            sockets: HashMap::new(),
+            next_token: FIRST_CONNECTION,
+            waker,
+            sender,
+            receiver,
+            workers: WorkerPool::new(workers),
+            acl,
+            limits,
+            manager_proxy,
+            shutdown,
+            deadline: None,
+            unnotified: Vec::new(),
+        })
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            match self.poll.poll(&mut events, Some(TICK)) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => result?,
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER if self.deadline.is_none() => self.accept()?,
+                    LISTENER => {}
+                    WAKER => self.drain_outbox(),
+                    token => {
+                        if event.is_readable() {
+                            self.read(token);
+                        }
+                        if event.is_writable() {
+                            self.flush(token);
+                        }
+                    }
+                }
+            }
+            match self.deadline {
+                None if self.shutdown.requested() => self.begin_shutdown(),
+                None => self.expire_sessions(),
+                Some(deadline) => {
+                    if self.drained(deadline) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let tokens: Vec<Token> = self.sockets.keys().cloned().collect();
+        for token in tokens {
+            self.close_socket(token);
+        }
+        // a worker stuck past the deadline is left behind rather than blocking the exit.
+        if self.workers.finished() {
+            self.workers.join();
+        }
+        println!("[mq] reactor stopped");
+        Ok(())
+    }
+
+    fn begin_shutdown(&mut self) {
+        println!(
+            "[mq] shutting down, {} connection(s) open",
+            self.sockets.len()
+        );
+        self.poll
+            .registry()
+            .deregister(&mut self.listener)
+            .unwrap_or(());
+        self.unnotified = self.sockets.keys().cloned().collect();
+        self.notify_shutdown();
+        self.workers.close();
+        self.deadline = Some(Instant::now() + self.shutdown.timeout);
+    }
+
+    // a connection busy with a frame is tried again on the next tick instead of waited for,
+    // so a slow worker can't hold the reactor past the deadline.
+    fn notify_shutdown(&mut self) {
+        let sockets = &self.sockets;
+        self.unnotified.retain(|token| {
+            let Some(socket) = sockets.get(token) else {
+                return false;
+            };
+            match socket.conn.try_lock() {
+                Ok(conn) => conn.notify_shutdown(),
+                Err(TryLockError::Poisoned(poisoned)) => {
+                    println!("[mq] recovered a poisoned lock");
+                    socket.conn.clear_poison();
+                    poisoned.into_inner().notify_shutdown();
+                }
+                Err(TryLockError::WouldBlock) => return true,
+            }
+            false
+        });
+    }
+
+    // frames already handed to the workers are finished first,
+    // then each connection is closed once its replies are written.
+    fn drained(&mut self, deadline: Instant) -> bool {
+        if Instant::now() >= deadline {
+            println!(
+                "[mq] shutdown timed out, dropping {} connection(s)",
+                self.sockets.len()
+            );
+            return true;
+        }
+        self.notify_shutdown();
+        if !self.workers.finished() {
+            return false;
+        }
+        for socket in self.sockets.values() {
+            socket.handle.close();
+        }
+        self.sockets.is_empty()
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        loop {
+            let (mut stream, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    println!("[mq] failed to accept connection: {}", e);
+                    return Ok(());
+                }
+            };
+
+            // Note: this is a hack to prevent self-connection
+            // in the meantime, this hack is just temporary.
+            if stream.local_addr().is_ok_and(|local| local == peer) {
+                continue;
+            }
+            if !self.acl.permits(&peer.ip()) {
+                println!("[mq] connection refused by network acl: {}", peer);
+                stream.shutdown(net::Shutdown::Both).unwrap_or(());
+                continue;
+            }
+
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            let outbox = Outbox::reactor(token, self.sender.clone(), self.waker.clone());
+            self.poll
+                .registry()
+                .register(&mut stream, token, Interest::READABLE)?;
+
+            let conn = match PhysicalConnectionFactory::new()
+                .set_manager_proxy(Some(self.manager_proxy.clone()))
+                .set_outbox(outbox)
+                .set_stream(stream)
+                .fetch()
+            {
+                Ok(conn) => conn,
+                Err(_) => continue,
+            };
+            let (conn, stream) = conn;
+            let handle = conn.handle.clone();
+            let conn = Arc::new(Mutex::new(conn));
+            self.manager_proxy.safe_write().add(conn.clone());
+
+            self.sockets.insert(
+                token,
+                Socket {
+                    stream,
+                    conn,
+                    handle,
+                    assembler: FrameAssembler::new(self.limits),
+                    outgoing: Vec::new(),
+                    closing: false,
+                },
+            );
+        }
+    }
+
+    fn read(&mut self, token: Token) {
+        let mut buf = vec![0u8; READ_CHUNK];
+        loop {
+            let socket = match self.sockets.get_mut(&token) {
+                Some(socket) => socket,
+                None => return,
+            };
+            match socket.stream.read(&mut buf) {
+                Ok(0) => {
+                    // dbg!("conn closed.");
+                    self.close_socket(token);
+                    return;
+                }
+                Ok(n) => {
+                    if socket.closing || self.deadline.is_some() {
+                        continue;
+                    }
+                    match socket.assembler.feed(&buf[..n]) {
+                        Ok(frames) => {
+                            for frame in frames {
+                                self.workers.dispatch(token.0, socket.conn.clone(), frame);
+                            }
+                        }
+                        Err(e) => {
+                            // the rest is read and thrown away until the error frame is out.
+                            println!("[mq] connection {}: {}", socket.handle.id, e);
+                            socket.handle.reject(e.status());
+                            socket.closing = true;
+                        }
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.close_socket(token);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self, token: Token) {
+        let socket = match self.sockets.get_mut(&token) {
+            Some(socket) => socket,
+            None => return,
+        };
+
+        while !socket.outgoing.is_empty() {
+            match socket.stream.write(&socket.outgoing) {
+                Ok(0) => break,
+                Ok(n) => {
+                    socket.outgoing.drain(0..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.close_socket(token);
+                    return;
+                }
+            }
+        }
+
+        if socket.outgoing.is_empty() && socket.closing {
+            self.close_socket(token);
+            return;
+        }
+
+        // only ask for writable events while there is something left to write.
+        let interest = if socket.outgoing.is_empty() {
+            Interest::READABLE
+        } else {
+            Interest::READABLE | Interest::WRITABLE
+        };
+        if self
+            .poll
+            .registry()
+            .reregister(&mut socket.stream, token, interest)
+            .is_err()
+        {
+            self.close_socket(token);
+        }
+    }
+
+    fn drain_outbox(&mut self) {
+        while let Ok((token, outgoing)) = self.receiver.try_recv() {
+            if let Some(socket) = self.sockets.get_mut(&token) {
+                match outgoing {
+                    Outgoing::Data(mut data) => socket.outgoing.append(&mut data),
+                    Outgoing::Close => socket.closing = true,
+                }
+                self.flush(token);
+            }
+        }
+    }
+
+    fn expire_sessions(&mut self) {
+        for socket in self.sockets.values() {
+            if socket.closing {
+                continue;
+            }
+            // a busy connection is checked by its worker before the next frame anyway.
+            if let Ok(conn) = socket.conn.try_lock() {
+                if conn.expired() {
+                    conn.close_expired();
+                }
+            }
+        }
+    }
+
+    fn close_socket(&mut self, token: Token) {
+        if let Some(mut socket) = self.sockets.remove(&token) {
+            socket.handle.set_closed();
+            self.manager_proxy.safe_write().remove(socket.handle.id);
+            self.poll
+                .registry()
+                .deregister(&mut socket.stream)
+                .unwrap_or(());
+            socket.stream.shutdown(net::Shutdown::Both).unwrap_or(());
+        }
+    }
+}
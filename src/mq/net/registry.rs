@@ -0,0 +1,147 @@
+use crate::mq::common::sync::SafeLock;
+use crate::mq::net::outbox::Outbox;
+use crate::mq::protocol::proto::DataHead;
+use crate::mq::protocol::protobase::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+const DEFAULT_SLICE_SIZE: u32 = 256;
+// what the broker pads its own frames to at most, whatever slice size a client asks for.
+pub const MAX_SLICE_SIZE: u32 = 64 * 1024;
+
+// the part of a connection the registry works with. it is shared with the
+// PhysicalConnection, so listing or closing never waits for a worker to finish a frame.
+pub struct ConnectionHandle {
+    pub id: u64,
+    pub peer_addr: SocketAddr,
+    pub connected_at: SystemTime,
+    user: Mutex<Option<String>>,
+    client_name: Mutex<Option<String>>,
+    slice_size: AtomicU32,
+
+    pub outbox: Outbox,
+    // set when the broker decided to close the connection, later frames are dropped.
+    closing: AtomicBool,
+    // set by the reactor once the socket is gone.
+    closed: AtomicBool,
+}
+
+impl fmt::Debug for ConnectionHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ConnectionHandle({}, {})", self.id, self.peer_addr)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub peer_addr: SocketAddr,
+    pub connected_at: SystemTime,
+    pub user: Option<String>,
+    pub client_name: Option<String>,
+}
+
+impl ConnectionHandle {
+    pub fn new(peer_addr: SocketAddr, outbox: Outbox) -> Arc<ConnectionHandle> {
+        Arc::new(ConnectionHandle {
+            id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+            peer_addr,
+            connected_at: SystemTime::now(),
+            user: Mutex::new(None),
+            client_name: Mutex::new(None),
+            slice_size: AtomicU32::new(DEFAULT_SLICE_SIZE),
+            outbox,
+            closing: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn set_user(&self, user: String) {
+        self.user.safe_lock().replace(user);
+    }
+
+    pub fn set_client_name(&self, client_name: String) {
+        self.client_name.safe_lock().replace(client_name);
+    }
+
+    pub fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            id: self.id,
+            peer_addr: self.peer_addr,
+            connected_at: self.connected_at,
+            user: self.user.safe_lock().clone(),
+            client_name: self.client_name.safe_lock().clone(),
+        }
+    }
+
+    // the slice size of the last frame from the client, for what the broker sends on its own.
+    pub fn slice_size(&self) -> u32 {
+        self.slice_size.load(Ordering::SeqCst)
+    }
+
+    pub fn set_slice_size(&self, slice_size: u32) {
+        if slice_size > 0 {
+            self.slice_size
+                .store(slice_size.min(MAX_SLICE_SIZE), Ordering::SeqCst);
+        }
+    }
+
+    // pads the body to whole slices of head.slice_size and writes it behind the head.
+    // a head without a slice size gets the one of the connection, none is over MAX_SLICE_SIZE.
+    pub fn send(&self, mut head: DataHead, buffer: &[u8]) {
+        if head.slice_size == 0 {
+            head.slice_size = self.slice_size();
+        }
+        head.slice_size = head.slice_size.min(MAX_SLICE_SIZE);
+        let slice_size = head.slice_size as usize;
+        // the length before padding, so binary bodies ending in \0 survive.
+        head.count = buffer.len() as u32;
+        let mut padded = buffer.len();
+        if !padded.is_multiple_of(slice_size) || buffer.is_empty() {
+            padded += slice_size - buffer.len() % slice_size;
+        }
+        head.slice_count = (padded / slice_size) as u32;
+
+        let mut head_serialized = head.serialize_vec();
+        head_serialized.reserve(padded);
+        head_serialized.extend_from_slice(buffer);
+        head_serialized.resize(head_serialized.len() + padded - buffer.len(), 0u8);
+        self.outbox.send(head_serialized);
+    }
+
+    // an error frame with the status, then the connection is closed.
+    pub fn reject(&self, status: u16) {
+        if self.closing() || self.closed() {
+            return;
+        }
+        let mut head = DataHead::deserialize([0u8; 256]);
+        head.msg_sign = status;
+        self.send(head, &[]);
+        self.close();
+    }
+
+    // whatever was queued before is still written, then the socket is shut down.
+    pub fn close(&self) -> bool {
+        if !self.closing.swap(true, Ordering::SeqCst) && !self.closed() {
+            self.outbox.close();
+            return true;
+        }
+        false
+    }
+
+    pub fn closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    pub fn set_closed(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
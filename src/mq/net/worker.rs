@@ -0,0 +1,60 @@
+use crate::mq::net::conn::PhysicalConnection;
+use crate::mq::net::frame::Frame;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+type Job = (Arc<Mutex<PhysicalConnection>>, Frame);
+
+// frames of one connection always go to the same worker,
+// so they are handled in the order they arrived.
+pub struct WorkerPool {
+    senders: Vec<Sender<Job>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> WorkerPool {
+        let mut senders = Vec::new();
+        let mut handles = Vec::new();
+        for i in 0..size.max(1) {
+            let (sender, receiver) = channel::<Job>();
+            let handle = thread::Builder::new()
+                .name(format!("mq-worker-{}", i))
+                .spawn(move || {
+                    while let Ok((conn, frame)) = receiver.recv() {
+                        PhysicalConnection::handle_caught(&conn, frame);
+                    }
+                })
+                .unwrap();
+            senders.push(sender);
+            handles.push(handle);
+        }
+        WorkerPool { senders, handles }
+    }
+
+    pub fn dispatch(&self, key: usize, conn: Arc<Mutex<PhysicalConnection>>, frame: Frame) {
+        if self.senders.is_empty() {
+            return;
+        }
+        let sender = &self.senders[key % self.senders.len()];
+        sender.send((conn, frame)).unwrap_or(());
+    }
+
+    // no more frames are taken, the workers exit once their queues are handled.
+    pub fn close(&mut self) {
+        self.senders.clear();
+    }
+
+    pub fn finished(&self) -> bool {
+        self.handles.iter().all(|handle| handle.is_finished())
+    }
+
+    pub fn join(&mut self) {
+        self.close();
+        for handle in self.handles.drain(..) {
+            handle.join().unwrap_or(());
+        }
+    }
+}
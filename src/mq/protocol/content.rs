@@ -0,0 +1,16 @@
+// how the body of a message is encoded, in the content_type field of the head.
+// the broker does not look inside the body, it only keeps the type with the message
+// and hands it back on fetch replies and deliveries.
+pub const UNSPECIFIED: u8 = 0;
+pub const JSON: u8 = 1;
+pub const MSGPACK: u8 = 2;
+pub const CBOR: u8 = 3;
+
+pub fn name(content_type: u8) -> &'static str {
+    match content_type {
+        JSON => "application/json",
+        MSGPACK => "application/msgpack",
+        CBOR => "application/cbor",
+        _ => "application/octet-stream",
+    }
+}
@@ -1,3 +1,5 @@
+pub mod content;
 pub mod proto;
 pub mod protobase;
 pub mod raw;
+pub mod status;
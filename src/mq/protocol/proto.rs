@@ -15,7 +15,7 @@ pub struct DataHead {
 
     pub route1: [u8; 32],
 
-    pub route2: [u8; 32], // '*' to match any; '!' to pause
+    pub route2: [u8; 32], // ignored; deeper paths go in the path line in front of the body
 
     pub route3: [u8; 32], // used as queue name
 
@@ -24,7 +24,8 @@ pub struct DataHead {
     pub count: u32,
     pub msg_sign: u16,
     pub ack: u16,
-    pub reserved: [u8; 16],
+    pub content_type: u8, // see protocol::content
+    pub reserved: [u8; 15],
 }
 
 impl DataHead {
@@ -56,11 +57,42 @@ impl DataHead {
             count,
             msg_sign,
             ack: 0,
-            reserved: [0u8; 16],
+            content_type: 0,
+            reserved: [0u8; 15],
         }
     }
 }
 
+// the head of a reply or delivery the broker sends on a channel of a host.
+// it only needs the name of the host, not a VirtualHost like DataHead::new.
+pub fn reply_head(
+    virtual_host: &[u8],
+    channel: [u8; 32],
+    slice_size: u32,
+    msg_sign: u16,
+) -> DataHead {
+    let mut host = virtual_host[..virtual_host.len().min(32)].to_vec();
+    host.resize(32, 0u8);
+    DataHead {
+        virtual_host: <[u8; 32]>::try_from(host).unwrap(),
+        channel,
+        version: [1u8, 0u8, 0u8, 0u8],
+        routing_mod: [0u8; 4],
+        command: [0u8; 24],
+        route0: [0u8; 32],
+        route1: [0u8; 32],
+        route2: [0u8; 32],
+        route3: [0u8; 32],
+        slice_count: 0,
+        slice_size,
+        count: 0,
+        msg_sign,
+        ack: 0,
+        content_type: 0,
+        reserved: [0u8; 15],
+    }
+}
+
 impl Serialize<256> for DataHead {
     fn serialize(&self) -> [u8; 256] {
         let serialized = self.serialize_vec();
@@ -83,6 +115,7 @@ impl Serialize<256> for DataHead {
         serialized.append(&mut self.count.to_le_bytes().to_vec());
         serialized.append(&mut self.msg_sign.to_le_bytes().to_vec());
         serialized.append(&mut self.ack.to_le_bytes().to_vec());
+        serialized.push(self.content_type);
         serialized.append(&mut self.reserved.to_vec());
         serialized
     }
@@ -126,7 +159,8 @@ impl Deserialize<256> for DataHead {
         let ack = <u16>::from_le_bytes(
             <[u8; 2]>::try_from(deserialized.drain(0..2).collect::<Vec<_>>()).unwrap(),
         );
-        let reserved = <[u8; 16]>::try_from(deserialized.drain(0..16).collect::<Vec<_>>()).unwrap();
+        let content_type = deserialized.remove(0);
+        let reserved = <[u8; 15]>::try_from(deserialized.drain(0..15).collect::<Vec<_>>()).unwrap();
 
         DataHead {
             virtual_host: virtual_host_sha256,
@@ -143,6 +177,7 @@ impl Deserialize<256> for DataHead {
             count,
             msg_sign,
             ack,
+            content_type,
             reserved,
         }
     }
@@ -275,3 +310,61 @@ impl Deserialize<512> for Data {
         }
     }
 }
+
+// prefixes every slice in multiplexed framing, so slices of different channels can interleave.
+pub struct SliceHead {
+    pub channel: [u8; 32],
+    pub seq: u32, // index of the slice within its message
+    pub len: u32,
+    pub reserved: [u8; 8],
+}
+
+impl SliceHead {
+    pub fn new(channel: [u8; 32], seq: u32, len: u32) -> SliceHead {
+        SliceHead {
+            channel,
+            seq,
+            len,
+            reserved: [0u8; 8],
+        }
+    }
+}
+
+impl Serialize<48> for SliceHead {
+    fn serialize(&self) -> [u8; 48] {
+        let serialized = self.serialize_vec();
+        <[u8; 48]>::try_from(serialized).unwrap()
+    }
+
+    fn serialize_vec(&self) -> Vec<u8> {
+        let mut serialized = vec![];
+        serialized.append(&mut self.channel.to_vec());
+        serialized.append(&mut self.seq.to_le_bytes().to_vec());
+        serialized.append(&mut self.len.to_le_bytes().to_vec());
+        serialized.append(&mut self.reserved.to_vec());
+        serialized
+    }
+}
+
+impl Deserialize<48> for SliceHead {
+    type T = SliceHead;
+
+    fn deserialize(data: [u8; 48]) -> Self::T {
+        let mut deserialized = data.to_vec();
+        let channel = <[u8; 32]>::try_from(deserialized.drain(0..32).collect::<Vec<_>>()).unwrap();
+        let seq = <u32>::from_le_bytes(
+            <[u8; 4]>::try_from(deserialized.drain(0..4).collect::<Vec<_>>()).unwrap(),
+        );
+        let len = <u32>::from_le_bytes(
+            <[u8; 4]>::try_from(deserialized.drain(0..4).collect::<Vec<_>>()).unwrap(),
+        );
+        let reserved = <[u8; 8]>::try_from(deserialized.drain(0..8).collect::<Vec<_>>()).unwrap();
+
+        SliceHead {
+            channel,
+            seq,
+            len,
+            reserved,
+        }
+    }
+}
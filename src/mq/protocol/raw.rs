@@ -1,4 +1,7 @@
+use crate::mq::net::registry::ConnectionHandle;
 use crate::mq::routing::key::RoutingKey;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct RawData {
@@ -7,6 +10,12 @@ pub struct RawData {
     pub virtual_host: String,
     pub routing_key: RoutingKey,
     pub io_type: IOType,
+    // kept with pushed messages, see protocol::content.
+    pub content_type: u8,
+    // None for data that did not come in over a connection.
+    pub peer_addr: Option<SocketAddr>,
+    // the connection the data came in on, consumers are registered against it.
+    pub connection: Option<Arc<ConnectionHandle>>,
 }
 
 #[derive(Debug)]
@@ -26,6 +35,9 @@ pub enum Raw {
 pub enum RawMessage {
     Push(Vec<u8>),
     Fetch(Vec<u8>),
+    // registers the channel as a consumer of the queue, until it is cancelled.
+    Subscribe(Vec<u8>),
+    Cancel(Vec<u8>),
     Nop,
 }
 
@@ -39,5 +51,8 @@ pub enum RawCommand {
     DropExchange(Vec<u8>),
     DropBinding(Vec<u8>),
 
+    // replies with the bindings made on the exchange.
+    ListBindings,
+
     Nop,
 }
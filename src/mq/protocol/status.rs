@@ -0,0 +1,40 @@
+// status codes sent back to the client in the msg_sign field of the feedback head.
+pub const OK: u16 = 0x0;
+pub const QUEUE_EMPTY: u16 = 0xf;
+
+pub const AUTH_REQUIRED: u16 = 0x10;
+pub const AUTH_FAILED: u16 = 0x11;
+pub const ACCESS_REFUSED: u16 = 0x12;
+pub const TOKEN_EXPIRED: u16 = 0x13;
+pub const SHUTTING_DOWN: u16 = 0x14;
+// what a command refers to does not exist, or it could not be parsed.
+pub const NOT_FOUND: u16 = 0x15;
+pub const BAD_REQUEST: u16 = 0x16;
+
+// the connection is closed right after these.
+pub const FRAME_ERROR: u16 = 0x20;
+pub const MESSAGE_TOO_LARGE: u16 = 0x21;
+pub const TOO_MANY_CHANNELS: u16 = 0x22;
+pub const BUFFER_LIMIT: u16 = 0x23;
+// the broker failed while handling a frame of the connection.
+pub const INTERNAL_ERROR: u16 = 0x24;
+
+pub fn name(status: u16) -> &'static str {
+    match status {
+        OK => "ok",
+        QUEUE_EMPTY => "queue empty",
+        AUTH_REQUIRED => "authentication required",
+        AUTH_FAILED => "authentication failed",
+        ACCESS_REFUSED => "access refused",
+        TOKEN_EXPIRED => "token expired",
+        SHUTTING_DOWN => "shutting down",
+        NOT_FOUND => "not found",
+        BAD_REQUEST => "bad request",
+        FRAME_ERROR => "frame error",
+        MESSAGE_TOO_LARGE => "message too large",
+        TOO_MANY_CHANNELS => "too many channels",
+        BUFFER_LIMIT => "buffer limit exceeded",
+        INTERNAL_ERROR => "internal error",
+        _ => "unknown status",
+    }
+}
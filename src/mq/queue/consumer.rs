@@ -0,0 +1,47 @@
+use crate::mq::net::registry::ConnectionHandle;
+use crate::mq::protocol::proto::reply_head;
+use crate::mq::queue::queue_object::QueueObject;
+use std::sync::Arc;
+
+// a channel of a connection that messages of a queue are pushed to.
+pub struct Consumer {
+    handle: Arc<ConnectionHandle>,
+    channel: String,
+}
+
+impl Consumer {
+    pub fn new(handle: Arc<ConnectionHandle>, channel: String) -> Consumer {
+        Consumer { handle, channel }
+    }
+
+    pub fn is(&self, connection: u64, channel: &str) -> bool {
+        self.handle.id == connection && self.channel == channel
+    }
+
+    pub fn alive(&self) -> bool {
+        !self.handle.closing() && !self.handle.closed()
+    }
+
+    // a delivery carries the consumer channel, the DELIVER command and the queue name in route3.
+    pub fn deliver(&self, queue: &str, obj: &QueueObject) {
+        let mut channel = self.channel.as_bytes().to_vec();
+        channel.resize(32, 0u8);
+        let mut command = b"DELIVER".to_vec();
+        command.resize(24, 0u8);
+        let mut route3 = [0u8; 32];
+        let name = &queue.as_bytes()[..queue.len().min(32)];
+        route3[..name.len()].copy_from_slice(name);
+
+        let mut head = reply_head(
+            obj.virtual_host.as_bytes(),
+            <[u8; 32]>::try_from(channel).unwrap(),
+            0,
+            0,
+        );
+        head.routing_mod = [0u8, 2u8, 0u8, 0u8];
+        head.command = <[u8; 24]>::try_from(command).unwrap();
+        head.route3 = route3;
+        head.content_type = obj.content_type;
+        self.handle.send(head, &obj.content);
+    }
+}
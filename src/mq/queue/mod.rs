@@ -1,3 +1,4 @@
+pub mod consumer;
 pub mod manager;
 pub mod qbase;
 pub mod queue_object;
@@ -1,9 +1,12 @@
+use crate::mq::queue::consumer::Consumer;
 use crate::mq::queue::queue_object::QueueObject;
 
 pub struct Queue {
     name: String,
     data: Vec<QueueObject>,
     len: u64,
+    consumers: Vec<Consumer>,
+    next_consumer: usize,
 }
 
 impl Queue {
@@ -12,10 +15,17 @@ impl Queue {
             name: String::from(name),
             data: Vec::new(),
             len: 0,
+            consumers: Vec::new(),
+            next_consumer: 0,
         }
     }
 
+    // messages only stay in the queue while nobody is subscribed.
     pub fn push_back(&mut self, value: QueueObject) {
+        let value = match self.deliver(value) {
+            Some(value) => value,
+            None => return,
+        };
         let data = &mut self.data;
         data.push(value);
         self.len += 1;
@@ -45,4 +55,38 @@ impl Queue {
     pub fn clear(&mut self) {
         self.data.clear();
     }
+
+    // whatever piled up before the first consumer arrived is handed out right away.
+    pub fn subscribe(&mut self, consumer: Consumer) {
+        self.consumers.push(consumer);
+        while let Some(value) = self.pop_front() {
+            if let Some(value) = self.deliver(value) {
+                self.data.insert(0, value);
+                self.len += 1;
+                break;
+            }
+        }
+    }
+
+    pub fn cancel(&mut self, connection: u64, channel: &str) -> bool {
+        let before = self.consumers.len();
+        self.consumers.retain(|c| !c.is(connection, channel));
+        self.consumers.len() != before
+    }
+
+    // round-robin over the consumers, the ones whose connection is gone are dropped on the way.
+    // the value is handed back when there is no consumer left to take it.
+    fn deliver(&mut self, value: QueueObject) -> Option<QueueObject> {
+        while !self.consumers.is_empty() {
+            let index = self.next_consumer % self.consumers.len();
+            if !self.consumers[index].alive() {
+                self.consumers.remove(index);
+                continue;
+            }
+            self.consumers[index].deliver(&self.name, &value);
+            self.next_consumer = index + 1;
+            return None;
+        }
+        Some(value)
+    }
 }
@@ -1,15 +1,25 @@
+use std::sync::Arc;
+
+// the content is shared, a message pushed to many queues is stored once.
 pub struct QueueObject {
     pub virtual_host: String,
-    pub content: Vec<u8>,
+    pub content: Arc<Vec<u8>>,
+    pub content_type: u8,
 }
 
 impl QueueObject {
     pub fn new(virtual_host: &String, content: Vec<u8>) -> QueueObject {
         QueueObject {
             virtual_host: virtual_host.clone(),
-            content,
+            content: Arc::new(content),
+            content_type: 0,
         }
     }
+
+    pub fn set_content_type(mut self, content_type: u8) -> Self {
+        self.content_type = content_type;
+        self
+    }
 }
 
 impl Clone for QueueObject {
@@ -17,6 +27,7 @@ impl Clone for QueueObject {
         QueueObject {
             virtual_host: self.virtual_host.clone(),
             content: self.content.clone(),
+            content_type: self.content_type,
         }
     }
 }
@@ -0,0 +1,149 @@
+use crate::mq::queue::qbase::Queue;
+use crate::mq::routing::exchange::Exchange;
+use crate::mq::routing::headers::HeaderMatch;
+use crate::mq::routing::key::RoutingKey;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BindingKind {
+    Queue,
+    Exchange,
+}
+
+impl BindingKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BindingKind::Queue => "queue",
+            BindingKind::Exchange => "exchange",
+        }
+    }
+}
+
+// what NewBinding and DropBinding carry: "queue a/b/q key" or "exchange a/c key".
+// the path of the target starts at the root of the host, the key "#" matches every message.
+// a key like "all:region=eu,tenant=acme" is matched against the headers of headers routes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindingSpec {
+    pub kind: BindingKind,
+    pub path: String,
+    pub key: String,
+}
+
+impl BindingSpec {
+    pub fn parse(data: &[u8]) -> Option<BindingSpec> {
+        let text = String::from_utf8_lossy(data);
+        let parts: Vec<&str> = text.trim_end_matches("\0").split_whitespace().collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let kind = match parts[0] {
+            "queue" => BindingKind::Queue,
+            "exchange" => BindingKind::Exchange,
+            _ => return None,
+        };
+        let path = parts[1].trim_matches('/').to_string();
+        if path.is_empty() {
+            return None;
+        }
+        Some(BindingSpec {
+            kind,
+            path,
+            key: parts[2].to_string(),
+        })
+    }
+
+    // the exchange names to the target, and the name of the target itself.
+    pub fn segments(&self) -> Vec<String> {
+        self.path.split('/').map(|s| s.to_string()).collect()
+    }
+
+    // one line of a binding listing, in the form it is parsed from.
+    pub fn describe(&self) -> String {
+        format!("{} {} {}", self.kind.name(), self.path, self.key)
+    }
+}
+
+pub enum BindingTarget {
+    Queue(Arc<RwLock<Queue>>),
+    Exchange(Arc<RwLock<Exchange>>),
+}
+
+// a queue or exchange bound to an exchange, it gets the messages routed there with a matching key.
+pub struct Binding {
+    pub spec: BindingSpec,
+    pub target: BindingTarget,
+    headers: Option<HeaderMatch>,
+}
+
+impl Binding {
+    pub fn new(spec: BindingSpec, target: BindingTarget) -> Binding {
+        let headers = HeaderMatch::parse(&spec.key);
+        Binding {
+            spec,
+            target,
+            headers,
+        }
+    }
+
+    // a fanout route matches every binding, '*' and '#' as the queue of a topic route as well.
+    // a headers route only matches bindings with a header match, and the ones keyed '#'.
+    pub fn matches(&self, routing: &RoutingKey, key: &str) -> bool {
+        if self.spec.key == "#" {
+            return true;
+        }
+        match routing {
+            RoutingKey::Headers(_, headers) => self
+                .headers
+                .as_ref()
+                .is_some_and(|matcher| matcher.matches(headers)),
+            RoutingKey::Fanout(_) => true,
+            RoutingKey::Topic(_) if key == "*" || key == "#" => true,
+            _ => self.spec.key == key,
+        }
+    }
+
+    // whether the binding goes to the queue or exchange at path, or to anything below the exchange.
+    pub fn targets(&self, kind: BindingKind, path: &str) -> bool {
+        let exact = self.spec.kind == kind && self.spec.path == path;
+        match kind {
+            BindingKind::Queue => exact,
+            BindingKind::Exchange => exact || self.spec.path.starts_with(&format!("{}/", path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_round_trip() {
+        for line in [
+            "queue a/b/q key",
+            "exchange a/c #",
+            "queue q all:region=eu,tenant=acme",
+        ] {
+            let spec = BindingSpec::parse(line.as_bytes()).unwrap();
+            assert_eq!(spec.describe(), line);
+            assert_eq!(BindingSpec::parse(spec.describe().as_bytes()), Some(spec));
+        }
+    }
+
+    #[test]
+    fn parse_trims_padding_and_slashes() {
+        let spec = BindingSpec::parse(b"exchange /a/c/ key\0\0\0").unwrap();
+        assert_eq!(spec.kind, BindingKind::Exchange);
+        assert_eq!(spec.path, "a/c");
+        assert_eq!(spec.key, "key");
+        assert_eq!(spec.segments(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_specs() {
+        assert!(BindingSpec::parse(b"").is_none());
+        assert!(BindingSpec::parse(b"queue a/q").is_none());
+        assert!(BindingSpec::parse(b"queue a/q key extra").is_none());
+        assert!(BindingSpec::parse(b"topic a/q key").is_none());
+        assert!(BindingSpec::parse(b"queue / key").is_none());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::mq::common::sync::SafeRwLock;
+use crate::mq::queue::qbase::Queue;
+use crate::mq::routing::key::RoutingKey;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// routes that resolve to queues are the only ones kept, but the queue slot of a fanout
+// route is free text, so the cache is bounded all the same.
+const MAX_ROUTES: usize = 4096;
+
+pub type Route = Arc<Vec<Arc<RwLock<Queue>>>>;
+
+// the queues a routing key resolved to, so a message is one hash lookup
+// instead of a walk through the exchange tree. it is shared by all exchanges of a host,
+// and any change to the topology drops every route, they are resolved again on demand.
+#[derive(Default)]
+pub struct RouteCache {
+    inner: RwLock<Routes>,
+}
+
+#[derive(Default)]
+struct Routes {
+    // bumped on every invalidation, a route resolved before that is not kept.
+    generation: u64,
+    routes: HashMap<RoutingKey, Route>,
+    // routes of reads, which never follow bindings.
+    unbound: HashMap<RoutingKey, Route>,
+}
+
+impl RouteCache {
+    pub fn new() -> RouteCache {
+        RouteCache::default()
+    }
+
+    // bindings tells routes that follow the bindings apart from the ones that don't.
+    pub fn resolve<F>(&self, key: &RoutingKey, bindings: bool, resolve: F) -> Option<Route>
+    where
+        F: FnOnce() -> Option<Vec<Arc<RwLock<Queue>>>>,
+    {
+        let generation = {
+            let inner = self.inner.safe_read();
+            if let Some(route) = inner.get(bindings).get(key) {
+                return Some(route.clone());
+            }
+            inner.generation
+        };
+
+        // resolved without holding the cache, the topology may change meanwhile.
+        let route: Route = Arc::new(resolve()?);
+        let mut inner = self.inner.safe_write();
+        if inner.generation == generation {
+            let routes = inner.get_mut(bindings);
+            if routes.len() >= MAX_ROUTES {
+                routes.clear();
+            }
+            routes.insert(key.clone(), route.clone());
+        }
+        Some(route)
+    }
+
+    pub fn invalidate(&self) {
+        let mut inner = self.inner.safe_write();
+        inner.generation += 1;
+        inner.routes.clear();
+        inner.unbound.clear();
+    }
+}
+
+impl Routes {
+    fn get(&self, bindings: bool) -> &HashMap<RoutingKey, Route> {
+        if bindings {
+            &self.routes
+        } else {
+            &self.unbound
+        }
+    }
+
+    fn get_mut(&mut self, bindings: bool) -> &mut HashMap<RoutingKey, Route> {
+        if bindings {
+            &mut self.routes
+        } else {
+            &mut self.unbound
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mq::routing::key::RoutingPath;
+    use std::cell::Cell;
+
+    fn key(queue: &str) -> RoutingKey {
+        RoutingKey::Direct(RoutingPath::new(vec![String::from("a")], queue.to_string()))
+    }
+
+    fn queue(name: &str) -> Vec<Arc<RwLock<Queue>>> {
+        vec![Arc::new(RwLock::new(Queue::new(&name.to_string())))]
+    }
+
+    #[test]
+    fn resolved_routes_are_kept() {
+        let cache = RouteCache::new();
+        let first = cache.resolve(&key("q"), true, || Some(queue("q"))).unwrap();
+        let second = cache
+            .resolve(&key("q"), true, || unreachable!("the route is cached"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // reads keep their routes apart, they never follow bindings.
+        let calls = Cell::new(0);
+        cache.resolve(&key("q"), false, || {
+            calls.set(calls.get() + 1);
+            Some(queue("q"))
+        });
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn route_resolved_across_an_invalidation_is_not_kept() {
+        let cache = RouteCache::new();
+        let route = cache.resolve(&key("q"), true, || {
+            cache.invalidate();
+            Some(queue("q"))
+        });
+        assert!(route.is_some());
+        assert!(cache.inner.safe_read().routes.is_empty());
+
+        let calls = Cell::new(0);
+        cache.resolve(&key("q"), true, || {
+            calls.set(calls.get() + 1);
+            Some(queue("q"))
+        });
+        assert_eq!(calls.get(), 1);
+        assert_eq!(cache.inner.safe_read().routes.len(), 1);
+    }
+
+    #[test]
+    fn routes_are_dropped_at_the_limit() {
+        let cache = RouteCache::new();
+        for i in 0..MAX_ROUTES {
+            cache.resolve(&key(&i.to_string()), true, || Some(queue("q")));
+        }
+        assert_eq!(cache.inner.safe_read().routes.len(), MAX_ROUTES);
+        cache.resolve(&key("one more"), true, || Some(queue("q")));
+        assert_eq!(cache.inner.safe_read().routes.len(), 1);
+        assert!(cache
+            .inner
+            .safe_read()
+            .routes
+            .contains_key(&key("one more")));
+    }
+}
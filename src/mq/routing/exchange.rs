@@ -1,31 +1,51 @@
+use crate::mq::common::sync::SafeRwLock;
 use crate::mq::queue::manager::QueueManager;
 use crate::mq::queue::qbase::Queue;
+use crate::mq::routing::binding::{Binding, BindingKind, BindingSpec, BindingTarget};
+use crate::mq::routing::cache::RouteCache;
 use crate::mq::routing::key::RoutingKey;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 pub struct Exchange {
     name: String,
+    // the names from the root of the host down to this exchange, "" for the root itself.
+    path: String,
     lower_exchange: HashMap<String, Arc<RwLock<Exchange>>>,
     queue_manager: Arc<RwLock<QueueManager>>,
     self_ref: Option<Arc<RwLock<Exchange>>>,
+    // a lock of their own, so they can be changed while the tree is only read.
+    bindings: RwLock<Vec<Binding>>,
+    // shared by every exchange of the host, dropped whenever the topology below changes.
+    routes: Arc<RouteCache>,
 }
 
 impl Exchange {
-    pub fn new(name: String) -> Exchange {
+    pub fn new(name: String, routes: Arc<RouteCache>) -> Exchange {
         Exchange {
             name,
+            path: String::new(),
             lower_exchange: HashMap::new(),
             queue_manager: Arc::from(RwLock::from(QueueManager::new())),
             self_ref: None,
+            bindings: RwLock::new(Vec::new()),
+            routes,
         }
     }
 
     pub fn add_exchange(&mut self, name: String) -> &mut Self {
         if !self.lower_exchange.contains_key(&name) {
-            let exc_ref = Arc::from(RwLock::from(Exchange::new(name.clone())));
-            exc_ref.write().unwrap().init(exc_ref.clone());
+            let exc_ref = Arc::from(RwLock::from(Exchange::new(
+                name.clone(),
+                self.routes.clone(),
+            )));
+            {
+                let mut exc = exc_ref.safe_write();
+                exc.init(exc_ref.clone());
+                exc.path = join(&self.path, &name);
+            }
             self.lower_exchange.insert(name, exc_ref.clone());
+            self.routes.invalidate();
         } else {
             println!("[mq] exchange already exists: {}", name)
         }
@@ -37,123 +57,358 @@ impl Exchange {
         self
     }
 
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
     pub fn get_exchange(&mut self, name: &String) -> Option<&mut Arc<RwLock<Exchange>>> {
         self.lower_exchange.get_mut(name)
     }
 
+    // the exchange at path below this one, by exact names.
+    pub fn find(&self, path: &[String]) -> Option<Arc<RwLock<Exchange>>> {
+        match path.split_first() {
+            None => self.self_ref.clone(),
+            Some((first, rest)) => self.lower_exchange.get(first)?.safe_read().find(rest),
+        }
+    }
+
     pub fn remove_exchange(&mut self, name: String) -> Option<Arc<RwLock<Exchange>>> {
-        for value in self.lower_exchange.values_mut() {
-            value.write().unwrap().remove_all_exchanges();
+        let removed = self.lower_exchange.remove(&name);
+        if let Some(value) = &removed {
+            value.safe_write().remove_all_exchanges();
         }
-        self.lower_exchange.remove(&name)
+        self.routes.invalidate();
+        removed
     }
 
     pub fn remove_all_exchanges(&mut self) -> &mut Self {
         for value in self.lower_exchange.values_mut() {
-            value.write().unwrap().remove_all_exchanges();
+            value.safe_write().remove_all_exchanges();
         }
         self.lower_exchange.clear();
         self
     }
 
     pub fn add_queue(&mut self, name: &String) -> &mut Self {
-        self.queue_manager.write().unwrap().add(name);
+        self.queue_manager.safe_write().add(name);
+        self.routes.invalidate();
         self
     }
 
     pub fn get_queue(&self, name: &String) -> Option<Arc<RwLock<Queue>>> {
-        self.queue_manager.read().unwrap().get(name)
+        self.queue_manager.safe_read().get(name)
     }
 
     pub fn remove_queue(&mut self, name: String) -> Option<Arc<RwLock<Queue>>> {
-        self.queue_manager.write().unwrap().remove(name)
+        self.routes.invalidate();
+        self.queue_manager.safe_write().remove(name)
     }
 
     pub fn get_all_queues(&self) -> Vec<Arc<RwLock<Queue>>> {
-        self.queue_manager.read().unwrap().get_all()
+        self.queue_manager.safe_read().get_all()
+    }
+
+    // the queues of this exchange and of every exchange below it.
+    pub fn get_all_queues_below(&self) -> Vec<Arc<RwLock<Queue>>> {
+        let mut queues = self.get_all_queues();
+        for value in self.lower_exchange.values() {
+            queues.append(&mut value.safe_read().get_all_queues_below());
+        }
+        queues
+    }
+
+    pub fn add_binding(&self, binding: Binding) {
+        let mut bindings = self.bindings.safe_write();
+        if bindings.iter().any(|b| b.spec == binding.spec) {
+            println!("[mq] binding already exists: {}", binding.spec.describe());
+            return;
+        }
+        bindings.push(binding);
+        self.routes.invalidate();
+    }
+
+    pub fn remove_binding(&self, spec: &BindingSpec) -> bool {
+        let mut bindings = self.bindings.safe_write();
+        let len = bindings.len();
+        bindings.retain(|b| b.spec != *spec);
+        self.routes.invalidate();
+        bindings.len() != len
+    }
+
+    pub fn get_bindings(&self) -> Vec<BindingSpec> {
+        self.bindings
+            .safe_read()
+            .iter()
+            .map(|b| b.spec.clone())
+            .collect()
+    }
+
+    // drops the bindings to a removed queue or exchange, here and in every exchange below.
+    pub fn unbind(&self, kind: BindingKind, path: &str) {
+        self.bindings
+            .safe_write()
+            .retain(|b| !b.targets(kind, path));
+        for value in self.lower_exchange.values() {
+            value.safe_read().unbind(kind, path);
+        }
+        self.routes.invalidate();
+    }
+
+    // adds the queues of this exchange a message goes to, together with the ones bound to it,
+    // and the bound exchanges the message has to be routed through as well. each of them once.
+    // without bindings only the queues of the exchange itself are added.
+    pub fn route(
+        &self,
+        routing: &RoutingKey,
+        queue_name: &String,
+        bindings: bool,
+        queues: &mut Vec<Arc<RwLock<Queue>>>,
+        exchanges: &mut Vec<Arc<RwLock<Exchange>>>,
+    ) {
+        let mut found = match routing {
+            RoutingKey::Fanout(_) if queue_name == "#" => self.get_all_queues_below(),
+            RoutingKey::Fanout(_) => self.get_all_queues(),
+            RoutingKey::Topic(_) if queue_name == "*" || queue_name == "#" => self.get_all_queues(),
+            // only the bindings decide where a headers route goes.
+            RoutingKey::Headers(..) => vec![],
+            _ => self.get_queue(queue_name).into_iter().collect(),
+        };
+
+        for binding in self.bindings.safe_read().iter() {
+            if bindings && binding.matches(routing, queue_name) {
+                match &binding.target {
+                    BindingTarget::Queue(queue) => found.push(queue.clone()),
+                    BindingTarget::Exchange(exc) => {
+                        if !exchanges.iter().any(|e| Arc::ptr_eq(e, exc)) {
+                            exchanges.push(exc.clone());
+                        }
+                    }
+                }
+            }
+        }
+        for queue in found {
+            if !queues.iter().any(|q| Arc::ptr_eq(q, &queue)) {
+                queues.push(queue);
+            }
+        }
     }
 
     pub fn clear_queue(&mut self, name: String) -> &mut Self {
         self.queue_manager
-            .write()
-            .unwrap()
+            .safe_write()
             .get(&name)
             .unwrap()
-            .write()
-            .unwrap()
+            .safe_write()
             .clear();
         self
     }
 
-    pub fn walk(&mut self, routing: RoutingKey, next: usize) -> Option<Vec<Arc<RwLock<Exchange>>>> {
-        if next > 2 {
-            return None;
-        }
-
-        let r: [String; 4];
-        let routing_cloned = routing.clone();
-        match routing {
-            RoutingKey::Direct(key) => r = key,
-            RoutingKey::Topic(key) => r = key,
-            RoutingKey::Fanout(key) => r = key,
-        }
-        let next_key = r[next].clone();
-        if (&next_key).starts_with("\0")
-            || next_key.is_empty()
-            || next_key.starts_with("!")
-            || next == 2
-        {
-            Some(vec![self.self_ref.clone()?])
-        } else if next_key.starts_with("*") {
-            let mut vec = vec![];
-            for value in self.lower_exchange.values().cloned() {
-                vec.push(value);
+    // exchanges below this one matching a topic pattern, '*' matches exactly one exchange
+    // and '#' any number of them, none included. an exchange matched twice is listed once.
+    pub fn walk_topic(&self, pattern: &[String], matched: &mut Vec<Arc<RwLock<Exchange>>>) {
+        match pattern.split_first() {
+            None => {
+                if let Some(self_ref) = &self.self_ref {
+                    if !matched.iter().any(|exc| Arc::ptr_eq(exc, self_ref)) {
+                        matched.push(self_ref.clone());
+                    }
+                }
+            }
+            Some((first, rest)) if first == "#" => {
+                self.walk_topic(rest, matched);
+                for value in self.lower_exchange.values() {
+                    value.safe_read().walk_topic(pattern, matched);
+                }
+            }
+            Some((first, rest)) if first == "*" => {
+                for value in self.lower_exchange.values() {
+                    value.safe_read().walk_topic(rest, matched);
+                }
+            }
+            Some((first, rest)) => {
+                if let Some(value) = self.lower_exchange.get(first) {
+                    value.safe_read().walk_topic(rest, matched);
+                }
             }
-            return Some(vec);
-        } else {
-            self.lower_exchange
-                .get_mut(&next_key)?
-                .write()
-                .unwrap()
-                .walk(routing_cloned, next + 1)
         }
     }
 
-    pub fn walk_readonly(
-        &self,
-        routing: RoutingKey,
-        next: usize,
-    ) -> Option<Vec<Arc<RwLock<Exchange>>>> {
-        if next > 2 {
-            return None;
-        }
+    // the exchange a routing key leads to, at any depth. takes read locks only,
+    // so any number of messages can be routed through the tree at once.
+    pub fn walk(&self, routing: &RoutingKey) -> Option<Arc<RwLock<Exchange>>> {
+        self.find(&routing.path().exchanges)
+    }
+}
 
-        let r: [String; 4];
-        let routing_cloned = routing.clone();
-        match routing {
-            RoutingKey::Direct(key) => r = key,
-            RoutingKey::Topic(key) => r = key,
-            RoutingKey::Fanout(key) => r = key,
-        }
-        let next_key = r[next].clone();
-        if (&next_key).starts_with("\0")
-            || next_key.is_empty()
-            || next_key.starts_with("!")
-            || next == 2
-        {
-            Some(vec![self.self_ref.clone()?])
-        } else if next_key.starts_with("*") {
-            let mut vec = vec![];
-            for value in self.lower_exchange.values().cloned() {
-                vec.push(value);
-            }
-            return Some(vec);
-        } else {
-            self.lower_exchange
-                .get(&next_key)?
-                .read()
-                .unwrap()
-                .walk_readonly(routing_cloned, next + 1)
+pub fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mq::routing::key::RoutingPath;
+
+    // orders/eu/de, orders/us and logs below the root, each with a queue of its own name
+    // and the root with "root".
+    fn tree() -> Arc<RwLock<Exchange>> {
+        let root = Arc::new(RwLock::new(Exchange::new(
+            String::from("mq-root"),
+            Arc::new(RouteCache::new()),
+        )));
+        root.safe_write().init(root.clone());
+        root.safe_write()
+            .add_exchange(String::from("orders"))
+            .add_exchange(String::from("logs"))
+            .add_queue(&String::from("root"));
+        let orders = root.safe_read().find(&[String::from("orders")]).unwrap();
+        orders
+            .safe_write()
+            .add_exchange(String::from("eu"))
+            .add_exchange(String::from("us"))
+            .add_queue(&String::from("orders"));
+        let eu = orders.safe_read().find(&[String::from("eu")]).unwrap();
+        eu.safe_write()
+            .add_exchange(String::from("de"))
+            .add_queue(&String::from("eu"));
+        for path in ["orders/us", "orders/eu/de", "logs"] {
+            let name = path.rsplit('/').next().unwrap().to_string();
+            at(&root, path).safe_write().add_queue(&name);
         }
+        root
+    }
+
+    fn at(root: &Arc<RwLock<Exchange>>, path: &str) -> Arc<RwLock<Exchange>> {
+        let path: Vec<String> = path.split('/').map(|x| x.to_string()).collect();
+        root.safe_read().find(&path).unwrap()
+    }
+
+    // the queue named after the exchange at path.
+    fn queue(root: &Arc<RwLock<Exchange>>, path: &str) -> Arc<RwLock<Queue>> {
+        let name = path.rsplit('/').next().unwrap().to_string();
+        at(root, path).safe_read().get_queue(&name).unwrap()
+    }
+
+    fn fanout(
+        root: &Arc<RwLock<Exchange>>,
+        path: &str,
+        queue_name: &str,
+    ) -> Vec<Arc<RwLock<Queue>>> {
+        let exc = at(root, path);
+        let routing = RoutingKey::Fanout(RoutingPath::new(
+            path.split('/').map(|x| x.to_string()).collect(),
+            queue_name.to_string(),
+        ));
+        let (mut queues, mut exchanges) = (vec![], vec![exc.clone()]);
+        exc.safe_read().route(
+            &routing,
+            &queue_name.to_string(),
+            true,
+            &mut queues,
+            &mut exchanges,
+        );
+        queues
+    }
+
+    fn same(queues: &[Arc<RwLock<Queue>>], expected: &[Arc<RwLock<Queue>>]) -> bool {
+        queues.len() == expected.len()
+            && expected
+                .iter()
+                .all(|q| queues.iter().any(|x| Arc::ptr_eq(x, q)))
+    }
+
+    fn matched(root: &Arc<RwLock<Exchange>>, pattern: &str) -> Vec<String> {
+        let pattern: Vec<String> = pattern
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect();
+        let mut matched = vec![];
+        root.safe_read().walk_topic(&pattern, &mut matched);
+        let mut paths: Vec<String> = matched
+            .iter()
+            .map(|exc| exc.safe_read().path().clone())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn exact_names_match_one_exchange() {
+        let root = tree();
+        assert_eq!(matched(&root, "orders/eu"), vec!["orders/eu"]);
+        assert_eq!(matched(&root, ""), vec![""]);
+        assert!(matched(&root, "orders/fr").is_empty());
+    }
+
+    #[test]
+    fn star_matches_exactly_one_exchange() {
+        let root = tree();
+        assert_eq!(matched(&root, "*"), vec!["logs", "orders"]);
+        assert_eq!(matched(&root, "orders/*"), vec!["orders/eu", "orders/us"]);
+        assert_eq!(matched(&root, "*/eu"), vec!["orders/eu"]);
+        assert_eq!(matched(&root, "*/*/*"), vec!["orders/eu/de"]);
+        assert!(matched(&root, "logs/*").is_empty());
+    }
+
+    #[test]
+    fn hash_matches_any_number_of_exchanges() {
+        let root = tree();
+        assert_eq!(
+            matched(&root, "#"),
+            vec![
+                "",
+                "logs",
+                "orders",
+                "orders/eu",
+                "orders/eu/de",
+                "orders/us"
+            ]
+        );
+        assert_eq!(
+            matched(&root, "orders/#"),
+            vec!["orders", "orders/eu", "orders/eu/de", "orders/us"]
+        );
+        assert_eq!(matched(&root, "#/de"), vec!["orders/eu/de"]);
+        // matched through several '#' expansions, listed once all the same.
+        assert_eq!(matched(&root, "#/#/de"), vec!["orders/eu/de"]);
+    }
+
+    #[test]
+    fn find_walks_exact_names() {
+        let root = tree();
+        let path = [
+            String::from("orders"),
+            String::from("eu"),
+            String::from("de"),
+        ];
+        let de = root.safe_read().find(&path).unwrap();
+        assert_eq!(de.safe_read().path(), "orders/eu/de");
+        assert!(root.safe_read().find(&path[1..]).is_none());
+    }
+
+    #[test]
+    fn fanout_reaches_the_queues_of_its_exchange() {
+        let root = tree();
+        let queues = fanout(&root, "orders", "");
+        assert!(same(&queues, &[queue(&root, "orders")]));
+        let queues = fanout(&root, "orders/eu", "anything");
+        assert!(same(&queues, &[queue(&root, "orders/eu")]));
+    }
+
+    #[test]
+    fn fanout_with_hash_reaches_every_queue_below() {
+        let root = tree();
+        let queues = fanout(&root, "orders", "#");
+        let expected =
+            ["orders", "orders/eu", "orders/eu/de", "orders/us"].map(|x| queue(&root, x));
+        assert!(same(&queues, &expected));
+        let queues = fanout(&root, "orders/eu/de", "#");
+        assert!(same(&queues, &[queue(&root, "orders/eu/de")]));
     }
 }
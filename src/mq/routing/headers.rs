@@ -0,0 +1,121 @@
+// message headers for headers routes, and the matches bindings make on them.
+// a headers route carries its headers in front of the body, one "key=value" line each,
+// ended by an empty line. the body behind it is what gets queued.
+
+pub type Headers = Vec<(String, String)>;
+
+// splits the header block off a body. a body without one has no headers.
+pub fn split_headers(body: Vec<u8>) -> (Headers, Vec<u8>) {
+    let mut headers = vec![];
+    let mut start = 0;
+    while let Some(end) = body[start..].iter().position(|b| *b == b'\n') {
+        let line = String::from_utf8_lossy(&body[start..start + end]).to_string();
+        start += end + 1;
+        if line.is_empty() {
+            return (headers, body[start..].to_vec());
+        }
+        match line.split_once('=') {
+            Some((key, value)) => headers.push((key.to_string(), value.to_string())),
+            None => break,
+        }
+    }
+    (vec![], body)
+}
+
+// the key of a binding for headers routes: "all:region=eu,tenant=acme" matches messages
+// carrying every one of the pairs, "any:..." messages carrying at least one of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderMatch {
+    all: bool,
+    pairs: Headers,
+}
+
+impl HeaderMatch {
+    pub fn parse(key: &str) -> Option<HeaderMatch> {
+        let (mode, pairs) = key.split_once(':')?;
+        let all = match mode {
+            "all" => true,
+            "any" => false,
+            _ => return None,
+        };
+        let pairs = pairs
+            .split(',')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect::<Option<Headers>>()?;
+        Some(HeaderMatch { all, pairs })
+    }
+
+    pub fn matches(&self, headers: &Headers) -> bool {
+        let mut found = self.pairs.iter().map(|pair| headers.contains(pair));
+        if self.all {
+            found.all(|x| x)
+        } else {
+            found.any(|x| x)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> Headers {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn all_needs_every_pair() {
+        let matcher = HeaderMatch::parse("all:region=eu,tenant=acme").unwrap();
+        assert!(matcher.matches(&headers(&[
+            ("region", "eu"),
+            ("tenant", "acme"),
+            ("x", "y")
+        ])));
+        assert!(!matcher.matches(&headers(&[("region", "eu")])));
+        assert!(!matcher.matches(&headers(&[("region", "eu"), ("tenant", "other")])));
+    }
+
+    #[test]
+    fn any_needs_one_pair() {
+        let matcher = HeaderMatch::parse("any:region=eu,tenant=acme").unwrap();
+        assert!(matcher.matches(&headers(&[("tenant", "acme")])));
+        assert!(!matcher.matches(&headers(&[("region", "us")])));
+        assert!(!matcher.matches(&headers(&[])));
+    }
+
+    #[test]
+    fn parse_rejects_other_keys() {
+        assert!(HeaderMatch::parse("orders").is_none());
+        assert!(HeaderMatch::parse("some:region=eu").is_none());
+        assert!(HeaderMatch::parse("all:region").is_none());
+    }
+
+    #[test]
+    fn split_headers_takes_the_block_off_the_body() {
+        let (found, body) = split_headers(b"region=eu\ntenant=acme\n\npayload".to_vec());
+        assert_eq!(found, headers(&[("region", "eu"), ("tenant", "acme")]));
+        assert_eq!(body, b"payload");
+
+        let (found, body) = split_headers(b"\npayload".to_vec());
+        assert!(found.is_empty());
+        assert_eq!(body, b"payload");
+    }
+
+    #[test]
+    fn body_without_a_header_block_is_kept() {
+        let (found, body) = split_headers(b"no headers\nhere".to_vec());
+        assert!(found.is_empty());
+        assert_eq!(body, b"no headers\nhere");
+
+        let (found, body) = split_headers(b"region=eu\nunfinished".to_vec());
+        assert!(found.is_empty());
+        assert_eq!(body, b"region=eu\nunfinished");
+    }
+}
@@ -1,16 +1,144 @@
-#[derive(Debug)]
+use crate::mq::routing::headers::Headers;
+
+// the exchanges from the root of the host down, and the queue name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RoutingPath {
+    pub exchanges: Vec<String>,
+    pub queue: String,
+}
+
+impl RoutingPath {
+    pub fn new(exchanges: Vec<String>, queue: String) -> RoutingPath {
+        RoutingPath { exchanges, queue }
+    }
+
+    // splits the "a/b/c/d" line a long path is sent as off the front of a body.
+    // a body without the line is malformed.
+    pub fn split_exchanges(mut body: Vec<u8>) -> Option<(Vec<String>, Vec<u8>)> {
+        let end = body.iter().position(|b| *b == b'\n')?;
+        let line = String::from_utf8_lossy(&body[..end]).to_string();
+        let exchanges = line
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect();
+        Some((exchanges, body.split_off(end + 1)))
+    }
+}
+
+// the four-slot form of the frame head: up to two exchanges, walked until the first
+// '\0', '!' or empty slot, and the queue name in the last slot. the third slot is never
+// walked, deeper paths are sent in front of the body.
+impl From<[String; 4]> for RoutingPath {
+    fn from(slots: [String; 4]) -> RoutingPath {
+        let [first, second, _, queue] = slots;
+        let exchanges = [first, second]
+            .into_iter()
+            .take_while(|x| !(x.is_empty() || x.starts_with("\0") || x.starts_with("!")))
+            .collect();
+        RoutingPath { exchanges, queue }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub enum RoutingKey {
-    Direct([String; 4]),
-    Topic([String; 4]),
-    Fanout([String; 4]),
+    Direct(RoutingPath),
+    Topic(RoutingPath),
+    Fanout(RoutingPath),
+    // goes to the queues bound to the exchange with a match on the headers of the message.
+    Headers(RoutingPath, Headers),
 }
 
 impl RoutingKey {
     pub(crate) fn clone(&self) -> RoutingKey {
         match self {
-            RoutingKey::Direct(arr) => RoutingKey::Direct(arr.clone()),
-            RoutingKey::Topic(arr) => RoutingKey::Topic(arr.clone()),
-            RoutingKey::Fanout(arr) => RoutingKey::Fanout(arr.clone()),
+            RoutingKey::Direct(path) => RoutingKey::Direct(path.clone()),
+            RoutingKey::Topic(path) => RoutingKey::Topic(path.clone()),
+            RoutingKey::Fanout(path) => RoutingKey::Fanout(path.clone()),
+            RoutingKey::Headers(path, headers) => {
+                RoutingKey::Headers(path.clone(), headers.clone())
+            }
+        }
+    }
+
+    pub fn path(&self) -> &RoutingPath {
+        match self {
+            RoutingKey::Direct(path) => path,
+            RoutingKey::Topic(path) => path,
+            RoutingKey::Fanout(path) => path,
+            RoutingKey::Headers(path, _) => path,
         }
     }
+
+    pub fn queue(&self) -> &String {
+        &self.path().queue
+    }
+
+    // the exchange names walked from the root, the queue is never part of it.
+    pub fn exchange_path(&self) -> Vec<String> {
+        self.path().exchanges.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slots(slots: [&str; 4]) -> [String; 4] {
+        slots.map(|x| x.to_string())
+    }
+
+    #[test]
+    fn from_walks_the_first_two_slots() {
+        let path = RoutingPath::from(slots(["a", "b", "c", "q"]));
+        assert_eq!(path.exchanges, vec!["a", "b"]);
+        assert_eq!(path.queue, "q");
+    }
+
+    #[test]
+    fn from_stops_at_an_empty_or_paused_slot() {
+        assert_eq!(
+            RoutingPath::from(slots(["a", "", "", "q"])).exchanges,
+            vec!["a"]
+        );
+        assert_eq!(
+            RoutingPath::from(slots(["a", "!", "", "q"])).exchanges,
+            vec!["a"]
+        );
+        assert_eq!(
+            RoutingPath::from(slots(["a", "\0x", "", "q"])).exchanges,
+            vec!["a"]
+        );
+        assert!(RoutingPath::from(slots(["", "b", "", "q"]))
+            .exchanges
+            .is_empty());
+    }
+
+    #[test]
+    fn split_exchanges_takes_the_path_line() {
+        let (exchanges, body) = RoutingPath::split_exchanges(b"a/b/c/d\npayload".to_vec()).unwrap();
+        assert_eq!(exchanges, vec!["a", "b", "c", "d"]);
+        assert_eq!(body, b"payload");
+
+        let (exchanges, body) = RoutingPath::split_exchanges(b"/a//b/\n".to_vec()).unwrap();
+        assert_eq!(exchanges, vec!["a", "b"]);
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn split_exchanges_without_a_line_is_malformed() {
+        assert!(RoutingPath::split_exchanges(b"a/b/c".to_vec()).is_none());
+        assert!(RoutingPath::split_exchanges(vec![]).is_none());
+    }
+
+    #[test]
+    fn keys_expose_their_path() {
+        let key = RoutingKey::Topic(RoutingPath::new(
+            vec!["orders".to_string(), "*".to_string()],
+            "q".to_string(),
+        ));
+        assert_eq!(key.exchange_path(), vec!["orders", "*"]);
+        assert_eq!(key.queue(), "q");
+        assert_eq!(key.clone(), key);
+    }
 }
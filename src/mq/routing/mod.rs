@@ -1,2 +1,5 @@
+pub mod binding;
+pub mod cache;
 pub mod exchange;
+pub mod headers;
 pub mod key;